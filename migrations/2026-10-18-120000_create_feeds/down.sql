@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE feed_items;
+DROP TABLE feed_subscriptions;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE feed_subscriptions (
+    url VARCHAR(255) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    author VARCHAR(32) NOT NULL,
+    created TIMESTAMP NOT NULL,
+    PRIMARY KEY (url, channel)
+);
+
+CREATE TABLE feed_items (
+    url VARCHAR(255) NOT NULL,
+    item CHAR(64) NOT NULL,
+    PRIMARY KEY (url, item)
+)
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE seen (
+    nick VARCHAR(32) PRIMARY KEY,
+    display_nick VARCHAR(32) NOT NULL,
+    channel VARCHAR(32) NOT NULL,
+    private BOOLEAN NOT NULL,
+    activity VARCHAR(16) NOT NULL,
+    content TEXT NOT NULL,
+    time TIMESTAMP NOT NULL
+)
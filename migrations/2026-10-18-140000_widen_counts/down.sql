@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE counts MODIFY name VARCHAR(32) NOT NULL
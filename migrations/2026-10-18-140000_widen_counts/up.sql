@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE counts MODIFY name VARCHAR(100) NOT NULL
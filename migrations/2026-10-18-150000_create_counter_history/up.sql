@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE counter_history (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100) NOT NULL,
+    author VARCHAR(32) NOT NULL,
+    delta BIGINT NOT NULL,
+    reason VARCHAR(255) NOT NULL,
+    time TIMESTAMP NOT NULL,
+    INDEX (name)
+)
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE urls (
+    id SERIAL PRIMARY KEY,
+    channel VARCHAR(50) NOT NULL,
+    url TEXT NOT NULL,
+    title TEXT,
+    poster VARCHAR(32) NOT NULL,
+    time TIMESTAMP NOT NULL,
+    INDEX (channel, time)
+)
@@ -0,0 +1,316 @@
+//! A local control socket which allows operators to inspect and
+//! control a running frippy without going through IRC.
+//!
+//! The protocol is line based, every command is a single line and
+//! every response consists of any number of non-empty lines followed by
+//! an empty line.
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use antidote::Mutex;
+use failure::{Fail, ResultExt};
+use irc::client::ext::ClientExt;
+use irc::client::{Client, IrcClient};
+use log::{error, info};
+
+use crate::error::*;
+use crate::plugin::PluginCommand;
+use crate::ThreadedPlugins;
+
+const HELP: &str = "\
+connections                                  List all connections
+plugins CONNECTION                           List the plugins of a connection
+say CONNECTION TARGET MESSAGE                Send a message to a channel or user
+join CONNECTION CHANNEL                      Join a channel
+part CONNECTION CHANNEL                      Leave a channel
+inspect CONNECTION PLUGIN                    Show the in-memory state of a plugin
+run CONNECTION CHANNEL NICK COMMAND          Run a command as if NICK typed it in CHANNEL
+help                                         Get this message";
+
+struct Connection {
+    name: String,
+    client: IrcClient,
+    plugins: ThreadedPlugins<IrcClient>,
+}
+
+/// Keeps track of all connections so they can be controlled through the socket.
+#[derive(Clone)]
+pub struct Controller {
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Controller {
+            connections: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+/// Binds a socket which only the current user can connect to.
+///
+/// The socket is created inside a directory only the current user can enter
+/// and moved into place once its permissions are restricted,
+/// so nobody can connect in between.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid socket path"))?;
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!(".{}.{}", name.to_string_lossy(), process::id()));
+
+    DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let temporary = dir.join("socket");
+    let result = UnixListener::bind(&temporary).and_then(|listener| {
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&temporary, path)?;
+        Ok(listener)
+    });
+
+    let _ = fs::remove_file(&temporary);
+    fs::remove_dir(&dir)?;
+
+    result
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Controller::default()
+    }
+
+    pub(crate) fn register(&self, client: &IrcClient, plugins: &ThreadedPlugins<IrcClient>) {
+        let name = client
+            .config()
+            .server
+            .clone()
+            .unwrap_or_else(|| String::from("unknown"));
+
+        self.connections.lock().push(Connection {
+            name,
+            client: client.clone(),
+            plugins: plugins.clone(),
+        });
+    }
+
+    /// Creates a Unix domain socket at `path` and handles
+    /// connections to it in a new thread.
+    pub fn listen<P: AsRef<Path>>(&self, path: P) -> Result<(), FrippyError> {
+        let path = path.as_ref();
+
+        // Only remove sockets left behind by a previous run
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            let error = if !metadata.file_type().is_socket() {
+                Some("the path exists and is not a socket")
+            } else if UnixStream::connect(path).is_ok() {
+                Some("another process is listening on the socket")
+            } else {
+                None
+            };
+
+            if let Some(error) = error {
+                let error = io::Error::new(io::ErrorKind::AddrInUse, error);
+                return Err(error.context(ErrorKind::Control).into());
+            }
+
+            fs::remove_file(path).context(ErrorKind::Control)?;
+        }
+
+        let listener = bind_private(path).context(ErrorKind::Control)?;
+
+        info!("Listening for control connections on {}", path.display());
+
+        let controller = self.clone();
+        thread::Builder::new()
+            .name(String::from("control"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let controller = controller.clone();
+                            thread::spawn(move || controller.handle_stream(stream));
+                        }
+                        Err(e) => error!("Failed to accept control connection: {}", e),
+                    }
+                }
+            })
+            .context(ErrorKind::ThreadSpawn)?;
+
+        Ok(())
+    }
+
+    fn handle_stream(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(e) => {
+                error!("Failed to clone control connection: {}", e);
+                return;
+            }
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            let response = self
+                .execute(line.trim())
+                .unwrap_or_else(|e| format!("error: {}", e));
+
+            let mut text = String::new();
+            for line in response.lines().filter(|l| !l.is_empty()) {
+                text.push_str(line);
+                text.push('\n');
+            }
+            text.push('\n');
+
+            if writer.write_all(text.as_bytes()).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn execute(&self, line: &str) -> Result<String, String> {
+        let mut parts = line.splitn(2, ' ');
+        let command = parts.next().unwrap_or("");
+        let args = parts.next().unwrap_or("");
+
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(HELP.to_owned()),
+            "connections" => Ok(self.list_connections()),
+            "plugins" => self.with_connection(args, 1, |c, _| Ok(c.plugins.to_string())),
+            "say" => self.with_connection(args, 3, |c, args| {
+                c.client
+                    .send_privmsg(args[0], args[1])
+                    .map(|_| String::from("Sent"))
+                    .map_err(|e| e.to_string())
+            }),
+            "join" => self.with_connection(args, 2, |c, args| {
+                c.client
+                    .send_join(args[0])
+                    .map(|_| format!("Joining {}", args[0]))
+                    .map_err(|e| e.to_string())
+            }),
+            "part" => self.with_connection(args, 2, |c, args| {
+                c.client
+                    .send_part(args[0])
+                    .map(|_| format!("Leaving {}", args[0]))
+                    .map_err(|e| e.to_string())
+            }),
+            "inspect" => self.with_connection(args, 2, |c, args| {
+                c.plugins
+                    .plugins
+                    .get(&args[0].to_lowercase())
+                    .map(|plugin| plugin.inspect())
+                    .ok_or_else(|| format!("{} was not found", args[0]))
+            }),
+            "run" => self.with_connection(args, 4, |c, args| {
+                let mut command = PluginCommand {
+                    source: args[1].to_owned(),
+                    hostmask: None,
+                    target: args[0].to_owned(),
+                    tokens: args[2].split(' ').map(ToOwned::to_owned).collect(),
+                };
+                let name = command.tokens[0].clone();
+
+                let sent = c
+                    .plugins
+                    .handle_command(&c.client, command.clone())
+                    .map_err(|e| e.to_string())?;
+                if sent {
+                    return Ok(String::from("Sent command"));
+                }
+
+                // Like "!name" in a channel, other commands run the factoid of that name
+                command.tokens.insert(0, String::from("exec"));
+                command.tokens.insert(0, String::from("factoid"));
+                match c.plugins.handle_command(&c.client, command) {
+                    Ok(true) => Ok(format!("Sent command to factoid {}", name)),
+                    Ok(false) => Err(format!("{} was not found", name)),
+                    Err(e) => Err(e.to_string()),
+                }
+            }),
+            _ => Err(String::from("Unknown command, send \"help\" for help")),
+        }
+    }
+
+    fn list_connections(&self) -> String {
+        self.connections
+            .lock()
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{}: {} as {}", i, c.name, c.client.current_nickname()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Splits `args` into `count` parts where the first selects
+    /// the connection either by index or by name.
+    /// The remaining parts are passed to `f`.
+    fn with_connection<F>(&self, args: &str, count: usize, f: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut Connection, &[&str]) -> Result<String, String>,
+    {
+        let args = args.splitn(count, ' ').collect::<Vec<_>>();
+        if args.len() < count || args.iter().any(|a| a.is_empty()) {
+            return Err(String::from("Not enough arguments, send \"help\" for help"));
+        }
+
+        let mut connections = self.connections.lock();
+        let connection = match args[0].parse::<usize>() {
+            Ok(i) => connections.get_mut(i),
+            Err(_) => connections.iter_mut().find(|c| c.name == args[0]),
+        }
+        .ok_or_else(|| format!("No connection {} was found", args[0]))?;
+
+        f(connection, &args[1..])
+    }
+}
+
+/// A connection to the control socket of a running frippy.
+pub struct ControlClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl ControlClient {
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+
+        Ok(ControlClient { reader, writer })
+    }
+
+    /// Sends a single command and returns the lines of the response.
+    pub fn send(&mut self, command: &str) -> io::Result<Vec<String>> {
+        writeln!(self.writer, "{}", command.replace('\n', " "))?;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The connection was closed",
+                ));
+            }
+
+            let line = line.trim_end_matches('\n');
+            if line.is_empty() {
+                return Ok(lines);
+            }
+
+            lines.push(line.to_owned());
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! An optional HTTP server which exposes the databases of the plugins
+//! through a small JSON API and a simple HTML page to manage them.
+//!
+//! Every request to the API needs to carry the configured token in an
+//! `Authorization: Bearer <token>` header.
+//!
+//! | Method   | Path                                          | Body                 |
+//! |----------|-----------------------------------------------|----------------------|
+//! | `GET`    | `/api/networks`                               |                      |
+//! | `GET`    | `/api/NETWORK/KIND?q=QUERY`                   |                      |
+//! | `PUT`    | `/api/NETWORK/factoids/NAME`                  | `{"content": "..."}` |
+//! | `DELETE` | `/api/NETWORK/factoids/NAME/IDX`              |                      |
+//! | `PUT`    | `/api/NETWORK/quotes/CHANNEL/QUOTEE/IDX`      | `{"content": "..."}` |
+//! | `DELETE` | `/api/NETWORK/quotes/CHANNEL/QUOTEE/IDX`      |                      |
+//! | `DELETE` | `/api/NETWORK/reminders/ID`                   |                      |
+//! | `DELETE` | `/api/NETWORK/tells/ID`                       |                      |
+//! | `PUT`    | `/api/NETWORK/counters/NAME`                  | `{"count": 5}`       |
+//! | `DELETE` | `/api/NETWORK/counters/NAME`                  |                      |
+//!
+//! `KIND` is one of `factoids`, `quotes`, `reminders`, `tells` or `counters`.
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+
+use antidote::RwLock;
+use chrono::Utc;
+use failure::{format_err, ResultExt};
+use log::{error, info};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::error::*;
+use crate::plugins::{counter, factoid, quote, remind, tell};
+
+static DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Bodies larger than this are rejected.
+const MAX_BODY: u64 = 64 * 1024;
+
+type ApiResult = Result<Value, (u16, String)>;
+
+/// Maps a plugin error to a 404 if it is of the `NotFound` kind
+/// and to a 500 otherwise.
+macro_rules! db_error {
+    ($not_found:path) => {
+        |e| {
+            if e.kind() == $not_found {
+                (404, e.to_string())
+            } else {
+                error!("Dashboard database error: {}", e);
+                (500, e.to_string())
+            }
+        }
+    };
+}
+
+/// The databases of a single network.
+pub struct Stores {
+    pub factoids: Arc<RwLock<dyn factoid::database::Database>>,
+    pub quotes: Arc<RwLock<dyn quote::database::Database>>,
+    pub reminders: Arc<RwLock<dyn remind::database::Database>>,
+    pub tells: Arc<RwLock<dyn tell::database::Database>>,
+    pub counters: Arc<RwLock<dyn counter::database::Database>>,
+}
+
+/// Serves the dashboard for all added networks.
+#[derive(Default)]
+pub struct Dashboard {
+    networks: Vec<(String, Stores)>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Dashboard::default()
+    }
+
+    /// Makes the databases of a network available through the dashboard.
+    pub fn add_network(&mut self, name: &str, stores: Stores) {
+        self.networks.push((name.to_owned(), stores));
+    }
+
+    /// Binds to `address` and handles requests in a new thread.
+    pub fn listen(self, address: &str, token: &str) -> Result<(), FrippyError> {
+        if token.is_empty() {
+            Err::<(), _>(format_err!("The dashboard token must not be empty"))
+                .context(ErrorKind::Dashboard)?;
+        }
+
+        let server = Server::http(address)
+            .map_err(|e| format_err!("{}", e))
+            .context(ErrorKind::Dashboard)?;
+
+        info!("Serving the dashboard on http://{}", address);
+
+        let token = token.to_owned();
+        thread::Builder::new()
+            .name(String::from("dashboard"))
+            .spawn(move || {
+                for request in server.incoming_requests() {
+                    self.handle(request, &token);
+                }
+            })
+            .context(ErrorKind::ThreadSpawn)?;
+
+        Ok(())
+    }
+
+    fn handle(&self, mut request: Request, token: &str) {
+        let url = request.url().to_owned();
+        let mut parts = url.splitn(2, '?');
+        let path = parts.next().unwrap_or("");
+        let query = parts
+            .next()
+            .and_then(|q| {
+                q.split('&')
+                    .filter_map(|p| {
+                        let mut pair = p.splitn(2, '=');
+                        match (pair.next(), pair.next()) {
+                            (Some("q"), Some(value)) => {
+                                Some(percent_decode(&value.replace('+', " ")))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .next()
+            })
+            .unwrap_or_default();
+
+        let response = if path == "/" {
+            Response::from_string(DASHBOARD_HTML).with_header(content_type("text/html"))
+        } else if path.starts_with("/api/") {
+            let result = if is_authorized(&request, token) {
+                let segments = path["/api/".len()..]
+                    .split('/')
+                    .map(percent_decode)
+                    .collect::<Vec<_>>();
+                let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+                let method = request.method().clone();
+                read_body(&mut request)
+                    .and_then(|body| self.route(&method, &segments, &query, &body))
+            } else {
+                Err((401, String::from("Invalid token")))
+            };
+
+            let (status, value) = match result {
+                Ok(value) => (200, value),
+                Err((status, message)) => (status, json!({ "error": message })),
+            };
+
+            Response::from_string(value.to_string())
+                .with_status_code(status)
+                .with_header(content_type("application/json"))
+        } else {
+            Response::from_string("Not found").with_status_code(404)
+        };
+
+        if let Err(e) = request.respond(response) {
+            error!("Failed to respond to dashboard request: {}", e);
+        }
+    }
+
+    fn route(&self, method: &Method, segments: &[&str], query: &str, body: &Value) -> ApiResult {
+        if let (Method::Get, ["networks"]) = (method, segments) {
+            return Ok(self
+                .networks
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect());
+        }
+
+        let (network, segments) = match segments.split_first() {
+            Some((&network, segments)) => (network, segments),
+            None => return Err((404, String::from("Not found"))),
+        };
+        let stores = self
+            .networks
+            .iter()
+            .find(|(name, _)| name == network)
+            .map(|(_, stores)| stores)
+            .ok_or_else(|| (404, format!("Network {} was not found", network)))?;
+
+        match (method, segments) {
+            (Method::Get, ["factoids"]) => list_factoids(stores, query),
+            (Method::Put, ["factoids", name]) => edit_factoid(stores, name, body),
+            (Method::Delete, ["factoids", name, idx]) => delete_factoid(stores, name, idx),
+            (Method::Get, ["quotes"]) => list_quotes(stores, query),
+            (Method::Put, ["quotes", channel, quotee, idx]) => {
+                edit_quote(stores, channel, quotee, idx, body)
+            }
+            (Method::Delete, ["quotes", channel, quotee, idx]) => {
+                delete_quote(stores, channel, quotee, idx)
+            }
+            (Method::Get, ["reminders"]) => list_reminders(stores, query),
+            (Method::Delete, ["reminders", id]) => delete_reminder(stores, id),
+            (Method::Get, ["tells"]) => list_tells(stores, query),
+            (Method::Delete, ["tells", id]) => delete_tell(stores, id),
+            (Method::Get, ["counters"]) => list_counters(stores, query),
+            (Method::Put, ["counters", name]) => edit_counter(stores, name, body),
+            (Method::Delete, ["counters", name]) => delete_counter(stores, name),
+            _ => Err((404, String::from("Not found"))),
+        }
+    }
+}
+
+fn list_factoids(stores: &Stores, query: &str) -> ApiResult {
+    let factoids = stores
+        .factoids
+        .read()
+        .search_factoids(query)
+        .map_err(db_error!(factoid::error::ErrorKind::NotFound))?;
+
+    Ok(factoids
+        .into_iter()
+        .map(|f| {
+            json!({
+                "name": f.name,
+                "idx": f.idx,
+                "content": f.content,
+                "author": f.author,
+                "created": f.created.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Factoids keep their history so editing one adds a new version.
+fn edit_factoid(stores: &Stores, name: &str, body: &Value) -> ApiResult {
+    let content = body_str(body, "content")?;
+    let mut factoids = stores.factoids.write();
+    let count = factoids
+        .count_factoids(name)
+        .map_err(db_error!(factoid::error::ErrorKind::NotFound))?;
+
+    let factoid = factoid::database::NewFactoid {
+        name,
+        idx: count,
+        content,
+        author: "dashboard",
+        created: Utc::now().naive_utc(),
+    };
+
+    factoids
+        .insert_factoid(&factoid)
+        .map_err(db_error!(factoid::error::ErrorKind::NotFound))?;
+
+    Ok(json!({ "idx": count }))
+}
+
+/// Only the latest version can be deleted because
+/// factoids are looked up by their number of versions.
+fn delete_factoid(stores: &Stores, name: &str, idx: &str) -> ApiResult {
+    let idx = parse_number(idx)?;
+    let mut factoids = stores.factoids.write();
+    let count = factoids
+        .count_factoids(name)
+        .map_err(db_error!(factoid::error::ErrorKind::NotFound))?;
+
+    if idx != count - 1 {
+        return Err((409, String::from("Only the latest version can be deleted")));
+    }
+
+    factoids
+        .delete_factoid(name, idx)
+        .map_err(db_error!(factoid::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn list_quotes(stores: &Stores, query: &str) -> ApiResult {
+    let quotes = stores
+        .quotes
+        .read()
+        .search_quotes(query)
+        .map_err(db_error!(quote::error::ErrorKind::NotFound))?;
+
+    Ok(quotes
+        .into_iter()
+        .map(|q| {
+            json!({
+                "quotee": q.quotee,
+                "channel": q.channel,
+                "idx": q.idx,
+                "content": q.content,
+                "author": q.author,
+                "created": q.created.to_string(),
+            })
+        })
+        .collect())
+}
+
+fn edit_quote(stores: &Stores, channel: &str, quotee: &str, idx: &str, body: &Value) -> ApiResult {
+    let idx = parse_number(idx)?;
+    let content = body_str(body, "content")?;
+
+    stores
+        .quotes
+        .write()
+        .update_quote(quotee, channel, idx, content)
+        .map_err(db_error!(quote::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+/// Like factoids only the latest quote of a user can be deleted
+/// to keep the numbering used in channels intact.
+fn delete_quote(stores: &Stores, channel: &str, quotee: &str, idx: &str) -> ApiResult {
+    let idx = parse_number(idx)?;
+    let mut quotes = stores.quotes.write();
+    let count = quotes
+        .count_user_quotes(quotee, channel)
+        .map_err(db_error!(quote::error::ErrorKind::NotFound))?;
+
+    if idx != count {
+        return Err((409, String::from("Only the latest quote can be deleted")));
+    }
+
+    quotes
+        .delete_quote(quotee, channel, idx)
+        .map_err(db_error!(quote::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn list_reminders(stores: &Stores, query: &str) -> ApiResult {
+    let events = stores
+        .reminders
+        .read()
+        .get_all_events()
+        .map_err(db_error!(remind::error::ErrorKind::NotFound))?;
+
+    let query = query.to_lowercase();
+    Ok(events
+        .into_iter()
+        .filter(|e| {
+            e.receiver.to_lowercase().contains(&query) || e.content.to_lowercase().contains(&query)
+        })
+        .map(|e| {
+            json!({
+                "id": e.id,
+                "receiver": e.receiver,
+                "content": e.content,
+                "author": e.author,
+                "time": e.time.to_string(),
+                "repeat": e.repeat,
+            })
+        })
+        .collect())
+}
+
+fn delete_reminder(stores: &Stores, id: &str) -> ApiResult {
+    let id = parse_number(id)?;
+
+    stores
+        .reminders
+        .write()
+        .delete_event(id)
+        .map_err(db_error!(remind::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn list_tells(stores: &Stores, query: &str) -> ApiResult {
+    let tells = stores.tells.read();
+    let mut receivers = tells
+        .get_receivers()
+        .map_err(db_error!(tell::error::ErrorKind::NotFound))?;
+    receivers.sort();
+    receivers.dedup();
+
+    let query = query.to_lowercase();
+    let mut list = Vec::new();
+    for receiver in receivers {
+        let messages = tells
+            .get_tells(&receiver)
+            .map_err(db_error!(tell::error::ErrorKind::NotFound))?;
+
+        list.extend(
+            messages
+                .into_iter()
+                .filter(|t| {
+                    t.receiver.to_lowercase().contains(&query)
+                        || t.sender.to_lowercase().contains(&query)
+                        || t.message.to_lowercase().contains(&query)
+                })
+                .map(|t| {
+                    json!({
+                        "id": t.id,
+                        "sender": t.sender,
+                        "receiver": t.receiver,
+                        "time": t.time.to_string(),
+                        "message": t.message,
+                    })
+                }),
+        );
+    }
+
+    Ok(Value::Array(list))
+}
+
+fn delete_tell(stores: &Stores, id: &str) -> ApiResult {
+    let id = parse_number(id)?;
+
+    stores
+        .tells
+        .write()
+        .delete_tell(id)
+        .map_err(db_error!(tell::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn list_counters(stores: &Stores, query: &str) -> ApiResult {
+    let counts = stores
+        .counters
+        .read()
+        .get_counts()
+        .map_err(db_error!(counter::error::ErrorKind::NotFound))?;
+
+    let query = query.to_lowercase();
+    Ok(counts
+        .into_iter()
+        .filter(|(name, _)| name.to_lowercase().contains(&query))
+        .map(|(name, count)| json!({ "name": name, "count": count }))
+        .collect())
+}
+
+fn edit_counter(stores: &Stores, name: &str, body: &Value) -> ApiResult {
+    let count = body
+        .get("count")
+        .and_then(Value::as_i64)
+        .ok_or_else(|| (400, String::from("Expected a number named \"count\"")))?;
+
+    stores
+        .counters
+        .write()
+        .set_count(name, count)
+        .map_err(db_error!(counter::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn delete_counter(stores: &Stores, name: &str) -> ApiResult {
+    stores
+        .counters
+        .write()
+        .delete_count(name)
+        .map_err(db_error!(counter::error::ErrorKind::NotFound))?;
+
+    Ok(Value::Null)
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+
+    request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv("Authorization"))
+        .any(|h| constant_time_eq(h.value.as_str().as_bytes(), expected.as_bytes()))
+}
+
+/// Compares two byte strings in a time which only depends on their length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn read_body(request: &mut Request) -> Result<Value, (u16, String)> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_string(&mut body)
+        .map_err(|e| (400, e.to_string()))?;
+
+    if body.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+
+    serde_json::from_str(&body).map_err(|e| (400, e.to_string()))
+}
+
+fn body_str<'a>(body: &'a Value, key: &str) -> Result<&'a str, (u16, String)> {
+    body.get(key)
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| (400, format!("Expected a string named \"{}\"", key)))
+}
+
+fn parse_number<T: std::str::FromStr>(number: &str) -> Result<T, (u16, String)> {
+    number
+        .parse()
+        .map_err(|_| (400, format!("{} is not a valid number", number)))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).expect("valid header")
+}
+
+/// Decodes the `%XX` escapes used in URLs.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = |b: u8| (b as char).to_digit(16);
+            if let (Some(high), Some(low)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -26,6 +26,22 @@ pub enum ErrorKind {
     #[fail(display = "Failed to spawn thread")]
     ThreadSpawn,
 
+    /// Control socket error
+    #[fail(display = "A control socket error has occured")]
+    Control,
+
+    /// Dashboard error
+    #[fail(display = "A dashboard error has occured")]
+    Dashboard,
+
+    /// Metrics error
+    #[fail(display = "A metrics error has occured")]
+    Metrics,
+
+    /// Webhook error
+    #[fail(display = "A webhook error has occured")]
+    Webhook,
+
     /// A Url error
     #[fail(display = "A Url error has occured")]
     Url,
@@ -46,7 +62,35 @@ pub enum ErrorKind {
     #[fail(display = "A Remind error has occured")]
     Remind,
 
+    /// A Logger error
+    #[fail(display = "A Logger error has occured")]
+    Logger,
+
+    /// A Seen error
+    #[fail(display = "A Seen error has occured")]
+    Seen,
+
+    /// A Urls error
+    #[fail(display = "A Urls error has occured")]
+    Urls,
+
+    /// A Feeds error
+    #[fail(display = "A Feeds error has occured")]
+    Feeds,
+
     /// A Counter error
     #[fail(display = "A Counter error has occured")]
     Counter,
+
+    /// An External error
+    #[fail(display = "An External error has occured")]
+    External,
+
+    /// A Native error
+    #[fail(display = "A Native error has occured")]
+    Native,
+
+    /// A Wasm error
+    #[fail(display = "A Wasm error has occured")]
+    Wasm,
 }
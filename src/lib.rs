@@ -32,11 +32,15 @@
 #[macro_use]
 extern crate diesel;
 
+pub mod control;
+pub mod dashboard;
 pub mod error;
+pub mod metrics;
 pub mod plugin;
 pub mod plugins;
 pub mod utils;
 
+use crate::control::Controller;
 use crate::plugin::*;
 
 use crate::error::*;
@@ -70,6 +74,7 @@ impl FrippyClient for IrcClient {
 pub struct Bot<'a> {
     prefix: &'a str,
     plugins: ThreadedPlugins<IrcClient>,
+    controller: Option<Controller>,
 }
 
 impl<'a> Bot<'a> {
@@ -90,6 +95,7 @@ impl<'a> Bot<'a> {
         Bot {
             prefix: cmd_prefix,
             plugins: ThreadedPlugins::new(),
+            controller: None,
         }
     }
 
@@ -126,6 +132,22 @@ impl<'a> Bot<'a> {
         self.plugins.remove(name)
     }
 
+    /// Registers the connection created by [`connect()`](#method.connect)
+    /// with a [`Controller`](control/struct.Controller.html)
+    /// so it can be managed through the control socket.
+    ///
+    /// # Examples
+    /// ```
+    /// use frippy::{control::Controller, Bot};
+    ///
+    /// let controller = Controller::new();
+    /// let mut bot = Bot::new(".");
+    /// bot.set_controller(controller.clone());
+    /// ```
+    pub fn set_controller(&mut self, controller: Controller) {
+        self.controller = Some(controller);
+    }
+
     /// This connects the `Bot` to IRC and creates a task on the
     /// [`IrcReactor`](../irc/client/reactor/struct.IrcReactor.html)
     /// which returns an Ok if the connection was cleanly closed and
@@ -166,6 +188,10 @@ impl<'a> Bot<'a> {
         let mut plugins = self.plugins.clone();
         let prefix = self.prefix.to_owned();
 
+        if let Some(ref controller) = self.controller {
+            controller.register(&client, &plugins);
+        }
+
         reactor.register_client_with_handler(client, move |client, message| {
             process_msg(client, &mut plugins, &prefix, message)
         });
@@ -184,6 +210,10 @@ fn process_msg<C>(
 where
     C: FrippyClient + 'static,
 {
+    metrics::MESSAGES
+        .with_label_values(&[&metrics::network(client)])
+        .inc();
+
     if let (Command::PRIVMSG(target, content), Some(options)) =
         (&message.command, &client.config().options)
     {
@@ -285,50 +315,65 @@ impl<C: FrippyClient + 'static> ThreadedPlugins<C> {
 
     /// Runs the execute functions on all plugins.
     /// Any errors that occur are printed right away.
+    ///
+    /// Plugins which require a thread are only started after all plugins
+    /// handled the message, so they can rely on what the others stored.
     pub fn execute_plugins(&mut self, client: &C, message: Message) {
         let message = Arc::new(message);
+        let network = metrics::network(client);
 
+        let mut threaded = Vec::new();
         for (name, plugin) in self.plugins.clone() {
             // Send the message to the plugin if the plugin needs it
             match plugin.execute(client, &message) {
                 ExecutionStatus::Done => (),
-                ExecutionStatus::Err(e) => log_error(&e),
-                ExecutionStatus::RequiresThread => {
-                    debug!(
-                        "Spawning thread to execute {} with {}",
-                        name,
-                        message.to_string().replace("\r\n", "")
-                    );
-
-                    // Clone everything before the move - the client uses an Arc internally too
-                    let plugin = Arc::clone(&plugin);
-                    let message = Arc::clone(&message);
-                    let client = client.clone();
-
-                    // Execute the plugin in another thread
-                    if let Err(e) = thread::Builder::new()
-                        .name(name)
-                        .spawn(move || {
-                            if let Err(e) = plugin.execute_threaded(&client, &message) {
-                                log_error(&e);
-                            } else {
-                                debug!("{} sent response from thread", plugin.name());
-                            }
-                        })
-                        .context(ErrorKind::ThreadSpawn)
-                    {
-                        log_error(&e.into());
-                    }
+                ExecutionStatus::Err(e) => {
+                    metrics::plugin_error(&network, &e);
+                    log_error(&e);
                 }
+                ExecutionStatus::RequiresThread => threaded.push((name, plugin)),
+            }
+        }
+
+        for (name, plugin) in threaded {
+            debug!(
+                "Spawning thread to execute {} with {}",
+                name,
+                message.to_string().replace("\r\n", "")
+            );
+
+            // Clone everything before the move - the client uses an Arc internally too
+            let message = Arc::clone(&message);
+            let client = client.clone();
+            let network = network.clone();
+
+            metrics::THREADS.with_label_values(&[&network, &name]).inc();
+
+            // Execute the plugin in another thread
+            if let Err(e) = thread::Builder::new()
+                .name(name)
+                .spawn(move || {
+                    if let Err(e) = plugin.execute_threaded(&client, &message) {
+                        metrics::plugin_error(&network, &e);
+                        log_error(&e);
+                    } else {
+                        debug!("{} sent response from thread", plugin.name());
+                    }
+                })
+                .context(ErrorKind::ThreadSpawn)
+            {
+                log_error(&e.into());
             }
         }
     }
 
+    /// Sends the command to the plugin named by its first token,
+    /// returns false if there is no such plugin.
     pub fn handle_command(
         &mut self,
         client: &C,
         mut command: PluginCommand,
-    ) -> Result<(), FrippyError> {
+    ) -> Result<bool, FrippyError> {
         // Check if there is a plugin for this command
         if let Some(plugin) = self.plugins.get(&command.tokens[0].to_lowercase()) {
             // The first token contains the name of the plugin
@@ -336,6 +381,15 @@ impl<C: FrippyClient + 'static> ThreadedPlugins<C> {
 
             debug!("Sending command \"{:?}\" to {}", command, name);
 
+            let network = metrics::network(client);
+            let label = name.to_lowercase();
+            metrics::COMMANDS
+                .with_label_values(&[&network, &label])
+                .inc();
+            metrics::THREADS
+                .with_label_values(&[&network, &label])
+                .inc();
+
             // Clone for the move - the client uses an Arc internally
             let client = client.clone();
             let plugin = Arc::clone(plugin);
@@ -343,13 +397,16 @@ impl<C: FrippyClient + 'static> ThreadedPlugins<C> {
                 .name(name)
                 .spawn(move || {
                     if let Err(e) = plugin.command(&client, command) {
+                        metrics::plugin_error(&network, &e);
                         log_error(&e);
                     };
                 })
                 .context(ErrorKind::ThreadSpawn)?;
+
+            return Ok(true);
         }
 
-        Ok(())
+        Ok(false)
     }
 }
 
@@ -1,22 +1,41 @@
 use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
 #[cfg(feature = "mysql")]
 use std::sync::Arc;
+use std::time::Duration;
 
 use glob::glob;
 use irc::client::reactor::IrcReactor;
 
-use frippy::plugins::counter::Counter;
-use frippy::plugins::factoid::Factoid;
+use frippy::plugins::counter::{self, Counter};
+use frippy::plugins::external::External;
+use frippy::plugins::factoid::{self, Factoid};
+use frippy::plugins::feeds::Feeds;
 use frippy::plugins::help::Help;
 use frippy::plugins::keepnick::KeepNick;
-use frippy::plugins::quote::Quote;
-use frippy::plugins::remind::Remind;
+use frippy::plugins::logger::Logger;
+use frippy::plugins::native::Native;
+use frippy::plugins::quote::{self, Quote};
+use frippy::plugins::remind::{self, Remind};
 use frippy::plugins::sed::Sed;
-use frippy::plugins::tell::Tell;
+use frippy::plugins::seen::Seen;
+use frippy::plugins::tell::{self, Tell};
 use frippy::plugins::unicode::Unicode;
-use frippy::plugins::url::UrlTitles;
+use frippy::plugins::url::forge::ForgeKind;
+use frippy::plugins::url::{DomainRule, UrlTitles};
+use frippy::plugins::urls::Urls;
+#[cfg(feature = "wasm")]
+use frippy::plugins::wasm::{Limits, Wasm};
+use frippy::plugins::webhook::Webhook;
 
 use failure::{bail, Error};
+use frippy::control::{ControlClient, Controller};
+use frippy::dashboard::{Dashboard, Stores};
+use frippy::error::log_error;
+use frippy::utils::{configure_http, HttpConfig};
 use frippy::Config;
 use log::{error, info};
 
@@ -28,6 +47,16 @@ extern crate diesel_migrations;
 embed_migrations!();
 
 fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("ctl") {
+        if let Err(e) = ctl(&args[1..]) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+
+        return;
+    }
+
     if let Err(e) = log4rs::init_file("log.yml", Default::default()) {
         use log4rs::Error;
         match e {
@@ -47,7 +76,60 @@ fn main() {
     }
 }
 
-fn run() -> Result<(), Error> {
+/// Talks to the control socket of a running frippy.
+/// Without a command an interactive console is started.
+fn ctl(args: &[String]) -> Result<(), Error> {
+    let (path, command) = match args.first().map(String::as_str) {
+        Some("-s") | Some("--socket") => match args.get(1) {
+            Some(path) => (path.clone(), &args[2..]),
+            None => bail!("Missing path after {}", args[0]),
+        },
+        _ => {
+            let path = load_configs()
+                .into_iter()
+                .filter_map(|c| c.options.and_then(|o| o.get("control_socket").cloned()))
+                .next();
+
+            match path {
+                Some(path) => (path, args),
+                None => bail!("No control_socket was configured, use --socket PATH"),
+            }
+        }
+    };
+
+    let mut client = ControlClient::connect(&path)?;
+
+    if !command.is_empty() {
+        for line in client.send(&command.join(" "))? {
+            println!("{}", line);
+        }
+
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    loop {
+        print!("frippy> ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+
+        let line = line.trim();
+        if line == "quit" || line == "exit" {
+            return Ok(());
+        }
+
+        for line in client.send(line)? {
+            println!("{}", line);
+        }
+    }
+}
+
+fn load_configs() -> Vec<Config> {
     // Load all toml files in the configs directory
     let mut configs = Vec::new();
     for toml in glob("configs/*.toml").unwrap() {
@@ -63,6 +145,53 @@ fn run() -> Result<(), Error> {
         }
     }
 
+    configs
+}
+
+/// Adds all plugins which need a database and returns
+/// their databases so they can be managed by the dashboard.
+fn add_database_plugins<F, Q, T, R, N>(
+    bot: &mut frippy::Bot,
+    factoids: F,
+    quotes: Q,
+    tells: T,
+    reminders: R,
+    counters: N,
+    counter_rules: counter::Rules,
+) -> Stores
+where
+    F: factoid::database::Database + 'static,
+    Q: quote::database::Database + 'static,
+    T: tell::database::Database + 'static,
+    R: remind::database::Database + 'static,
+    N: counter::database::Database + 'static,
+{
+    let factoid = Factoid::new(factoids);
+    let quote = Quote::new(quotes);
+    let tell = Tell::new(tells);
+    let remind = Remind::new(reminders);
+    let counter = Counter::new(counters, counter_rules);
+
+    let stores = Stores {
+        factoids: factoid.database(),
+        quotes: quote.database(),
+        tells: tell.database(),
+        reminders: remind.database(),
+        counters: counter.database(),
+    };
+
+    bot.add_plugin(factoid);
+    bot.add_plugin(quote);
+    bot.add_plugin(tell);
+    bot.add_plugin(remind);
+    bot.add_plugin(counter);
+
+    stores
+}
+
+fn run() -> Result<(), Error> {
+    let configs = load_configs();
+
     // Without configs the bot would just idle
     if configs.is_empty() {
         bail!("No config file was found");
@@ -70,12 +199,34 @@ fn run() -> Result<(), Error> {
 
     // Create an event loop to run the connections on.
     let mut reactor = IrcReactor::new()?;
+    let controller = Controller::new();
+    let mut control_socket = None;
+    let mut dashboard = Dashboard::new();
+    let mut dashboard_options = None;
+    let mut metrics_address = None;
+    let mut http_configured = false;
 
     // Open a connection and add work for each config
     for config in configs {
         let mut prefix = None;
         let mut disabled_plugins = None;
         let mut mysql_url = None;
+        let mut external_plugins = Vec::new();
+        let mut native_plugin_dir = None;
+        let mut wasm_plugin_dir = None;
+        let mut webhook = None;
+        let mut logger = None;
+        let mut feeds_interval = Duration::from_secs(600);
+        let mut seen_private_channels = Vec::new();
+        let mut counter_rules = counter::Rules::default();
+        let mut url_cache_ttl = Duration::from_secs(3600);
+        let mut url_first_poster = false;
+        let mut url_max_urls = 3;
+        let mut url_rules = Vec::new();
+        let mut url_forges = Vec::new();
+        let mut url_oembed_providers = Vec::new();
+        #[cfg(feature = "wasm")]
+        let mut wasm_limits = Limits::default();
         if let Some(ref options) = config.options {
             if let Some(disabled) = options.get("disabled_plugins") {
                 disabled_plugins = Some(disabled.split(',').map(|p| p.trim()).collect::<Vec<_>>());
@@ -83,51 +234,283 @@ fn run() -> Result<(), Error> {
             prefix = options.get("prefix");
 
             mysql_url = options.get("mysql_url");
+            if let Some(path) = options.get("control_socket") {
+                control_socket = Some(path.clone());
+            }
+            if let Some(address) = options.get("metrics_address") {
+                metrics_address = Some(address.clone());
+            }
+            if let Some(token) = options.get("dashboard_token") {
+                let address = options
+                    .get("dashboard_address")
+                    .cloned()
+                    .unwrap_or_else(|| String::from("127.0.0.1:8080"));
+                dashboard_options = Some((address, token.clone()));
+            }
+            native_plugin_dir = options.get("native_plugin_dir");
+            wasm_plugin_dir = options.get("wasm_plugin_dir");
+
+            #[cfg(feature = "wasm")]
+            {
+                if let Some(fuel) = options.get("wasm_fuel").and_then(|f| f.parse().ok()) {
+                    wasm_limits.fuel = fuel;
+                }
+                if let Some(kib) = options
+                    .get("wasm_memory_kib")
+                    .and_then(|m| m.parse::<usize>().ok())
+                {
+                    wasm_limits.memory = kib * 1024;
+                }
+            }
+
+            if let Some(secs) = options.get("feeds_interval").and_then(|s| s.parse().ok()) {
+                feeds_interval = Duration::from_secs(secs);
+            }
+
+            if let Some(per_channel) = options.get("counter_per_channel") {
+                counter_rules.per_channel = per_channel == "true";
+            }
+            if let Some(secs) = options.get("counter_cooldown").and_then(|s| s.parse().ok()) {
+                counter_rules.cooldown = Duration::from_secs(secs);
+            }
+            if let Some(ignored) = options.get("counter_ignore") {
+                counter_rules.ignored = ignored
+                    .split(',')
+                    .map(|n| n.trim().to_owned())
+                    .filter(|n| !n.is_empty())
+                    .collect();
+            }
+
+            if let Some(secs) = options.get("url_cache_ttl").and_then(|s| s.parse().ok()) {
+                url_cache_ttl = Duration::from_secs(secs);
+            }
+            if let Some(max_urls) = options.get("url_max_urls").and_then(|s| s.parse().ok()) {
+                url_max_urls = max_urls;
+            }
+            if let Some(first_poster) = options.get("url_first_poster") {
+                url_first_poster = first_poster == "true";
+            }
+            // Rules are configured as "url_rule_domain" or "url_rule_#channel/domain"
+            for (key, value) in options.iter().filter(|(k, _)| k.starts_with("url_rule_")) {
+                let mut split = key["url_rule_".len()..].rsplitn(2, '/');
+                let domain = split.next().unwrap().to_owned();
+                let channel = split.next().map(str::to_owned);
+
+                match value.parse::<DomainRule>() {
+                    Ok(rule) => url_rules.push((channel, domain, rule)),
+                    Err(e) => error!("Invalid rule \"{}\" for {}: {}", value, key, e),
+                }
+            }
+            // Forges are configured as "url_forge_host" = "kind api"
+            for (key, value) in options.iter().filter(|(k, _)| k.starts_with("url_forge_")) {
+                let mut split = value.split_whitespace();
+                match (split.next().and_then(ForgeKind::from_name), split.next()) {
+                    (Some(kind), Some(api)) => url_forges.push((
+                        key["url_forge_".len()..].to_owned(),
+                        kind,
+                        api.to_owned(),
+                    )),
+                    _ => error!("Invalid forge \"{}\" for {}", value, key),
+                }
+            }
+
+            // Providers are configured as "url_oembed_name" = "endpoint scheme..."
+            for (key, value) in options.iter().filter(|(k, _)| k.starts_with("url_oembed_")) {
+                let mut split = value.split_whitespace();
+                match split.next() {
+                    Some(endpoint) => url_oembed_providers.push((
+                        endpoint.to_owned(),
+                        split.map(str::to_owned).collect::<Vec<_>>(),
+                    )),
+                    None => error!("No oEmbed endpoint was set for {}", key),
+                }
+            }
+
+            // The client is shared so only the first config setting it is used
+            if !http_configured && options.keys().any(|k| k.starts_with("http_")) {
+                http_configured = true;
+
+                let mut http = HttpConfig::default();
+                if let Some(user_agent) = options.get("http_user_agent") {
+                    http.user_agent = user_agent.clone();
+                }
+                http.proxy = options.get("http_proxy").cloned();
+                if let Some(max) = options
+                    .get("http_max_redirects")
+                    .and_then(|s| s.parse().ok())
+                {
+                    http.max_redirects = max;
+                }
+                if let Some(retries) = options.get("http_retries").and_then(|s| s.parse().ok()) {
+                    http.retries = retries;
+                }
+                if let Some(ms) = options.get("http_backoff_ms").and_then(|s| s.parse().ok()) {
+                    http.backoff = Duration::from_millis(ms);
+                }
+                if let Some(languages) = options.get("http_accept_language") {
+                    http.accept_language = languages.clone();
+                }
+                if let Err(e) = configure_http(http) {
+                    error!("Failed to configure the HTTP client: {}", e);
+                }
+            }
+
+            if let Some(hosts) = options.get("url_allowed_hosts") {
+                frippy::utils::allow_hosts(&hosts.split(',').collect::<Vec<_>>());
+            }
+
+            if let Some(channels) = options.get("seen_private_channels") {
+                seen_private_channels = channels.split(',').map(|c| c.trim()).collect();
+            }
+
+            if let Some(address) = options.get("webhook_address") {
+                let secret = options.get("webhook_secret").map_or("", String::as_str);
+
+                // Repositories are configured as "webhook_owner/name"
+                let channels = options
+                    .iter()
+                    .filter(|(k, _)| k.starts_with("webhook_") && k.contains('/'))
+                    .map(|(k, v)| {
+                        let channels = v
+                            .split(',')
+                            .map(|c| c.trim().to_owned())
+                            .filter(|c| !c.is_empty())
+                            .collect();
+                        (k["webhook_".len()..].to_owned(), channels)
+                    })
+                    .collect();
+
+                webhook = Some(Webhook::new(address, secret, channels));
+            }
+
+            if let Some(dir) = options.get("log_dir") {
+                let excluded = options
+                    .get("log_exclude_channels")
+                    .map(|c| c.split(',').map(|c| c.trim()).collect::<Vec<_>>())
+                    .unwrap_or_default();
+
+                logger = Some(Logger::new(dir, &excluded));
+            }
+
+            if let Some(names) = options.get("external_plugins") {
+                let timeout = options
+                    .get("external_timeout")
+                    .and_then(|t| t.parse().ok())
+                    .unwrap_or(10);
+
+                for name in names.split(',').map(|n| n.trim()).filter(|n| !n.is_empty()) {
+                    match options.get(&format!("external_{}", name)) {
+                        Some(command) => external_plugins.push(External::new(
+                            name,
+                            command,
+                            Duration::from_secs(timeout),
+                        )),
+                        None => error!("No command was set for external plugin \"{}\"", name),
+                    }
+                }
+            }
         }
         let prefix = prefix.cloned().unwrap_or_else(|| String::from("."));
 
         let mut bot = frippy::Bot::new(&prefix);
+        bot.set_controller(controller.clone());
         bot.add_plugin(Help::new());
-        bot.add_plugin(UrlTitles::new(1024));
+        let url_titles = url_rules.into_iter().fold(
+            UrlTitles::new(1024)
+                .max_urls(url_max_urls)
+                .cache_ttl(url_cache_ttl)
+                .track_posters(url_first_poster),
+            |titles, (channel, domain, rule)| titles.rule(channel.as_deref(), &domain, rule),
+        );
+        let url_titles = url_forges
+            .into_iter()
+            .fold(url_titles, |titles, (host, kind, api)| {
+                titles.forge(&host, kind, &api)
+            });
+        let mut url_titles =
+            url_oembed_providers
+                .into_iter()
+                .fold(url_titles, |titles, (endpoint, schemes)| {
+                    let schemes = schemes.iter().map(String::as_str).collect::<Vec<_>>();
+                    titles.oembed_provider(&endpoint, &schemes)
+                });
         bot.add_plugin(Sed::new(60));
         bot.add_plugin(Unicode::new());
         bot.add_plugin(KeepNick::new());
 
+        let mut stores = None;
+
         #[cfg(feature = "mysql")]
         {
             if let Some(url) = mysql_url {
                 use diesel::MysqlConnection;
+                use frippy::metrics::QueryTimer;
                 use r2d2_diesel::ConnectionManager;
 
+                let network = config
+                    .server
+                    .as_ref()
+                    .map(String::as_str)
+                    .unwrap_or("unknown");
                 let manager = ConnectionManager::<MysqlConnection>::new(url.clone());
-                match r2d2::Pool::builder().build(manager) {
+                match r2d2::Pool::builder()
+                    .event_handler(Box::new(QueryTimer::new(network)))
+                    .build(manager)
+                {
                     Ok(pool) => match embedded_migrations::run(&*pool.get()?) {
                         Ok(_) => {
                             let pool = Arc::new(pool);
-                            bot.add_plugin(Factoid::new(pool.clone()));
-                            bot.add_plugin(Quote::new(pool.clone()));
-                            bot.add_plugin(Tell::new(pool.clone()));
-                            bot.add_plugin(Remind::new(pool.clone()));
-                            bot.add_plugin(Counter::new(pool.clone()));
+                            stores = Some(add_database_plugins(
+                                &mut bot,
+                                pool.clone(),
+                                pool.clone(),
+                                pool.clone(),
+                                pool.clone(),
+                                pool.clone(),
+                                counter_rules,
+                            ));
+                            bot.add_plugin(Feeds::new(pool.clone(), feeds_interval));
+                            bot.add_plugin(Seen::new(pool.clone(), &seen_private_channels));
+                            let urls = Urls::new(pool.clone());
+                            url_titles = url_titles.history(urls.database());
+                            bot.add_plugin(urls);
                             info!("Connected to MySQL server")
                         }
                         Err(e) => {
-                            bot.add_plugin(Factoid::new(HashMap::new()));
-                            bot.add_plugin(Quote::new(HashMap::new()));
-                            bot.add_plugin(Tell::new(HashMap::new()));
-                            bot.add_plugin(Remind::new(HashMap::new()));
-                            bot.add_plugin(Counter::new(HashMap::new()));
+                            stores = Some(add_database_plugins(
+                                &mut bot,
+                                HashMap::new(),
+                                HashMap::new(),
+                                HashMap::new(),
+                                HashMap::new(),
+                                HashMap::new(),
+                                counter_rules,
+                            ));
+                            bot.add_plugin(Feeds::new(HashMap::new(), feeds_interval));
+                            bot.add_plugin(Seen::new(HashMap::new(), &seen_private_channels));
+                            let urls = Urls::new(HashMap::new());
+                            url_titles = url_titles.history(urls.database());
+                            bot.add_plugin(urls);
                             error!("Failed to run migrations: {}", e);
                         }
                     },
                     Err(e) => error!("Failed to connect to database: {}", e),
                 }
             } else {
-                bot.add_plugin(Factoid::new(HashMap::new()));
-                bot.add_plugin(Quote::new(HashMap::new()));
-                bot.add_plugin(Tell::new(HashMap::new()));
-                bot.add_plugin(Remind::new(HashMap::new()));
-                bot.add_plugin(Counter::new(HashMap::new()));
+                stores = Some(add_database_plugins(
+                    &mut bot,
+                    HashMap::new(),
+                    HashMap::new(),
+                    HashMap::new(),
+                    HashMap::new(),
+                    HashMap::new(),
+                    counter_rules,
+                ));
+                bot.add_plugin(Feeds::new(HashMap::new(), feeds_interval));
+                bot.add_plugin(Seen::new(HashMap::new(), &seen_private_channels));
+                let urls = Urls::new(HashMap::new());
+                url_titles = url_titles.history(urls.database());
+                bot.add_plugin(urls);
             }
         }
         #[cfg(not(feature = "mysql"))]
@@ -135,11 +518,66 @@ fn run() -> Result<(), Error> {
             if mysql_url.is_some() {
                 error!("frippy was not built with the mysql feature")
             }
-            bot.add_plugin(Factoid::new(HashMap::new()));
-            bot.add_plugin(Quote::new(HashMap::new()));
-            bot.add_plugin(Tell::new(HashMap::new()));
-            bot.add_plugin(Remind::new(HashMap::new()));
-            bot.add_plugin(Counter::new(HashMap::new()));
+            stores = Some(add_database_plugins(
+                &mut bot,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                counter_rules,
+            ));
+            bot.add_plugin(Feeds::new(HashMap::new(), feeds_interval));
+            bot.add_plugin(Seen::new(HashMap::new(), &seen_private_channels));
+            let urls = Urls::new(HashMap::new());
+            url_titles = url_titles.history(urls.database());
+            bot.add_plugin(urls);
+        }
+
+        // Added after the databases are set up so it can add titles to stored URLs
+        bot.add_plugin(url_titles);
+
+        if let Some(webhook) = webhook {
+            bot.add_plugin(webhook);
+        }
+
+        if let Some(logger) = logger {
+            bot.add_plugin(logger);
+        }
+
+        for plugin in external_plugins {
+            bot.add_plugin(plugin);
+        }
+
+        if let Some(dir) = native_plugin_dir {
+            match Native::load_dir(Path::new(dir)) {
+                Ok(plugins) => {
+                    for plugin in plugins {
+                        bot.add_plugin(plugin);
+                    }
+                }
+                Err(e) => error!("Failed to load native plugins from {}: {}", dir, e),
+            }
+        }
+
+        #[cfg(feature = "wasm")]
+        {
+            if let Some(dir) = wasm_plugin_dir {
+                match Wasm::load_dir(Path::new(dir), wasm_limits) {
+                    Ok(plugins) => {
+                        for plugin in plugins {
+                            bot.add_plugin(plugin);
+                        }
+                    }
+                    Err(e) => error!("Failed to load wasm plugins from {}: {}", dir, e),
+                }
+            }
+        }
+        #[cfg(not(feature = "wasm"))]
+        {
+            if wasm_plugin_dir.is_some() {
+                error!("frippy was not built with the wasm feature")
+            }
         }
 
         if let Some(disabled_plugins) = disabled_plugins {
@@ -150,9 +588,36 @@ fn run() -> Result<(), Error> {
             }
         }
 
+        if let Some(stores) = stores {
+            let network = config
+                .server
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or("unknown");
+            dashboard.add_network(network, stores);
+        }
+
         bot.connect(&mut reactor, &config)?;
     }
 
+    if let Some(path) = control_socket {
+        if let Err(e) = controller.listen(&path) {
+            log_error(&e);
+        }
+    }
+
+    if let Some(address) = metrics_address {
+        if let Err(e) = frippy::metrics::listen(&address) {
+            log_error(&e);
+        }
+    }
+
+    if let Some((address, token)) = dashboard_options {
+        if let Err(e) = dashboard.listen(&address, &token) {
+            log_error(&e);
+        }
+    }
+
     // Run the bots until they throw an error - an error could be loss of connection
     reactor.run()?;
 
@@ -0,0 +1,147 @@
+//! Prometheus metrics which can be scraped from `/metrics`.
+use std::thread;
+
+use failure::{format_err, ResultExt};
+use irc::client::Client;
+use lazy_static::lazy_static;
+use log::{error, info};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use tiny_http::{Header, Response, Server};
+
+use crate::error::*;
+
+lazy_static! {
+    pub static ref MESSAGES: IntCounterVec =
+        register_int_counter_vec!("frippy_messages_total", "Messages processed", &["network"])
+            .unwrap();
+    pub static ref COMMANDS: IntCounterVec = register_int_counter_vec!(
+        "frippy_commands_total",
+        "Commands handled by each plugin",
+        &["network", "plugin"]
+    )
+    .unwrap();
+    pub static ref PLUGIN_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "frippy_plugin_errors_total",
+        "Errors returned by plugins",
+        &["network", "kind"]
+    )
+    .unwrap();
+    pub static ref THREADS: IntCounterVec = register_int_counter_vec!(
+        "frippy_threads_spawned_total",
+        "Threads spawned to run plugins",
+        &["network", "plugin"]
+    )
+    .unwrap();
+    pub static ref LUA_EXECUTIONS: IntCounterVec = register_int_counter_vec!(
+        "frippy_lua_executions_total",
+        "Lua factoids which were executed",
+        &["network"]
+    )
+    .unwrap();
+    pub static ref LUA_TIMEOUTS: IntCounterVec = register_int_counter_vec!(
+        "frippy_lua_timeouts_total",
+        "Lua factoids which timed out",
+        &["network"]
+    )
+    .unwrap();
+    pub static ref LUA_MEMORY_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "frippy_lua_memory_errors_total",
+        "Lua factoids which ran out of memory",
+        &["network"]
+    )
+    .unwrap();
+    pub static ref URL_FETCH: HistogramVec = register_histogram_vec!(
+        "frippy_url_fetch_seconds",
+        "Time taken to download urls",
+        &["network"]
+    )
+    .unwrap();
+    pub static ref DATABASE_QUERY: HistogramVec = register_histogram_vec!(
+        "frippy_database_query_seconds",
+        "Time a database connection was in use for a query",
+        &["network"]
+    )
+    .unwrap();
+}
+
+/// Returns the label used to tell the metrics of different connections apart.
+pub fn network<C: Client>(client: &C) -> String {
+    client
+        .config()
+        .server
+        .clone()
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+/// Counts an error returned by a plugin by its kind.
+pub fn plugin_error(network: &str, e: &FrippyError) {
+    PLUGIN_ERRORS
+        .with_label_values(&[network, &format!("{:?}", e.kind())])
+        .inc();
+}
+
+/// Records how long database connections of a network were checked out.
+#[cfg(feature = "mysql")]
+#[derive(Debug)]
+pub struct QueryTimer {
+    network: String,
+}
+
+#[cfg(feature = "mysql")]
+impl QueryTimer {
+    pub fn new(network: &str) -> Self {
+        QueryTimer {
+            network: network.to_owned(),
+        }
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl r2d2::HandleEvent for QueryTimer {
+    fn handle_checkin(&self, event: r2d2::event::CheckinEvent) {
+        DATABASE_QUERY
+            .with_label_values(&[&self.network])
+            .observe(event.duration().as_secs_f64());
+    }
+}
+
+/// Binds to `address` and serves the metrics in a new thread.
+pub fn listen(address: &str) -> Result<(), FrippyError> {
+    let server = Server::http(address)
+        .map_err(|e| format_err!("{}", e))
+        .context(ErrorKind::Metrics)?;
+
+    info!("Serving metrics on http://{}/metrics", address);
+
+    thread::Builder::new()
+        .name(String::from("metrics"))
+        .spawn(move || {
+            for request in server.incoming_requests() {
+                let response = if request.url() == "/metrics" {
+                    let encoder = TextEncoder::new();
+                    let mut buffer = Vec::new();
+                    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+                        error!("Failed to encode metrics: {}", e);
+                    }
+
+                    let header =
+                        Header::from_bytes(&b"Content-Type"[..], encoder.format_type().as_bytes())
+                            .expect("valid header");
+
+                    Response::from_data(buffer).with_header(header)
+                } else {
+                    Response::from_string("Not found").with_status_code(404)
+                };
+
+                if let Err(e) = request.respond(response) {
+                    error!("Failed to respond to metrics request: {}", e);
+                }
+            }
+        })
+        .context(ErrorKind::ThreadSpawn)?;
+
+    Ok(())
+}
@@ -33,6 +33,10 @@ pub trait Plugin: PluginName + Send + Sync + fmt::Debug {
     /// Similar to [`command()`](trait.Plugin.html#tymethod.command) but return a String instead of
     /// sending messages directly to IRC.
     fn evaluate(&self, client: &Self::Client, command: PluginCommand) -> Result<String, String>;
+    /// Describes the in-memory state of the plugin for the control socket.
+    fn inspect(&self) -> String {
+        format!("{:?}", self)
+    }
 }
 
 /// `PluginName` is required by [`Plugin`](trait.Plugin.html).
@@ -57,6 +61,8 @@ pub trait PluginName {
 pub struct PluginCommand {
     /// The sender of the command.
     pub source: String,
+    /// The full prefix of the sender like "nick!user@host" if it is known.
+    pub hostmask: Option<String>,
     /// If the command was sent to a channel, this will be that channel
     /// otherwise it is the same as `source`.
     pub target: String,
@@ -83,6 +89,7 @@ impl PluginCommand {
 
             Some(PluginCommand {
                 source: message.source_nickname().unwrap().to_string(),
+                hostmask: message.prefix.clone(),
                 target: message.response_target().unwrap().to_string(),
                 tokens,
             })
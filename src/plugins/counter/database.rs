@@ -13,29 +13,161 @@ use r2d2::Pool;
 #[cfg(feature = "mysql")]
 use r2d2_diesel::ConnectionManager;
 
+#[cfg(feature = "mysql")]
+use crate::utils::escape_like;
+
+use chrono::NaiveDateTime;
+
 use super::error::*;
 
+#[cfg_attr(feature = "mysql", derive(Queryable))]
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub id: i64,
+    pub name: String,
+    pub author: String,
+    pub delta: i64,
+    pub reason: String,
+    pub time: NaiveDateTime,
+}
+
+#[cfg_attr(feature = "mysql", derive(Insertable))]
+#[cfg_attr(feature = "mysql", table_name = "counter_history")]
+pub struct NewChange<'a> {
+    pub name: &'a str,
+    pub author: &'a str,
+    pub delta: i64,
+    pub reason: &'a str,
+    pub time: NaiveDateTime,
+}
+
+/// A counter and the changes made to it.
+#[derive(Clone, Debug, Default)]
+pub struct Count {
+    pub count: i64,
+    pub history: Vec<Change>,
+}
+
 pub trait Database: Send + Sync {
     fn add(&mut self, name: &str) -> Result<i64, CounterError>;
     fn subtract(&mut self, name: &str) -> Result<i64, CounterError>;
     fn get_count(&self, name: &str) -> Result<i64, CounterError>;
+    fn get_counts(&self) -> Result<Vec<(String, i64)>, CounterError>;
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError>;
+    fn delete_count(&mut self, name: &str) -> Result<(), CounterError>;
+    /// Returns the `limit` highest counts of a namespace, highest first.
+    fn get_top(&self, namespace: &str, limit: usize) -> Result<Vec<(String, i64)>, CounterError>;
+    /// Returns the `limit` lowest counts of a namespace, lowest first.
+    fn get_bottom(&self, namespace: &str, limit: usize)
+        -> Result<Vec<(String, i64)>, CounterError>;
+    /// Returns the position of a counter when its namespace is sorted
+    /// from highest to lowest and the number of counters in the namespace.
+    fn get_rank(&self, namespace: &str, name: &str) -> Result<(i64, i64), CounterError>;
+    fn insert_change(&mut self, change: &NewChange) -> Result<(), CounterError>;
+    /// Returns the `limit` newest changes of a counter which have a reason.
+    fn get_reasons(&self, name: &str, limit: usize) -> Result<Vec<Change>, CounterError>;
 }
 
-impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, i64, S> {
+/// Counters of a channel are named `"#channel name"`,
+/// global counters use the empty namespace and contain no spaces.
+pub fn in_namespace(name: &str, namespace: &str) -> bool {
+    if namespace.is_empty() {
+        !name.contains(' ')
+    } else {
+        name.len() > namespace.len()
+            && name.starts_with(namespace)
+            && name[namespace.len()..].starts_with(' ')
+    }
+}
+
+impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Count, S> {
     fn add(&mut self, name: &str) -> Result<i64, CounterError> {
-        Ok(*self
-            .entry(name.to_owned())
-            .and_modify(|count| *count += 1)
-            .or_insert(1))
+        let count = self.entry(name.to_owned()).or_default();
+        count.count += 1;
+        Ok(count.count)
     }
     fn subtract(&mut self, name: &str) -> Result<i64, CounterError> {
-        Ok(*self
-            .entry(name.to_owned())
-            .and_modify(|count| *count -= 1)
-            .or_insert(-1))
+        let count = self.entry(name.to_owned()).or_default();
+        count.count -= 1;
+        Ok(count.count)
     }
     fn get_count(&self, name: &str) -> Result<i64, CounterError> {
-        Ok(self.get(name).copied().unwrap_or(0))
+        Ok(self.get(name).map_or(0, |c| c.count))
+    }
+    fn get_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+        let mut counts = self
+            .iter()
+            .map(|(name, count)| (name.clone(), count.count))
+            .collect::<Vec<_>>();
+
+        counts.sort();
+        Ok(counts)
+    }
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+        self.entry(name.to_owned()).or_default().count = count;
+        Ok(())
+    }
+    fn delete_count(&mut self, name: &str) -> Result<(), CounterError> {
+        match self.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::NotFound)?,
+        }
+    }
+    fn get_top(&self, namespace: &str, limit: usize) -> Result<Vec<(String, i64)>, CounterError> {
+        let mut counts = self.get_counts()?;
+
+        counts.retain(|(name, _)| in_namespace(name, namespace));
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(limit);
+        Ok(counts)
+    }
+    fn get_bottom(
+        &self,
+        namespace: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, CounterError> {
+        let mut counts = self.get_counts()?;
+
+        counts.retain(|(name, _)| in_namespace(name, namespace));
+        counts.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(limit);
+        Ok(counts)
+    }
+    fn get_rank(&self, namespace: &str, name: &str) -> Result<(i64, i64), CounterError> {
+        let count = self.get(name).ok_or(ErrorKind::NotFound)?.count;
+        let counts = self
+            .iter()
+            .filter(|(name, _)| in_namespace(name, namespace))
+            .map(|(_, count)| count.count)
+            .collect::<Vec<_>>();
+        let higher = counts.iter().filter(|&&c| c > count).count();
+
+        Ok((higher as i64 + 1, counts.len() as i64))
+    }
+    fn insert_change(&mut self, change: &NewChange) -> Result<(), CounterError> {
+        let history = &mut self.entry(change.name.to_owned()).or_default().history;
+
+        history.push(Change {
+            id: history.len() as i64,
+            name: change.name.to_owned(),
+            author: change.author.to_owned(),
+            delta: change.delta,
+            reason: change.reason.to_owned(),
+            time: change.time,
+        });
+
+        Ok(())
+    }
+    fn get_reasons(&self, name: &str, limit: usize) -> Result<Vec<Change>, CounterError> {
+        Ok(self.get(name).map_or_else(Vec::new, |c| {
+            c.history
+                .iter()
+                .rev()
+                .filter(|c| !c.reason.is_empty())
+                .take(limit)
+                .cloned()
+                .collect()
+        }))
     }
 }
 
@@ -49,10 +181,21 @@ mod schema {
             count -> Bigint,
         }
     }
+
+    diesel::table! {
+        counter_history (id) {
+            id -> Bigint,
+            name -> Varchar,
+            author -> Varchar,
+            delta -> Bigint,
+            reason -> Varchar,
+            time -> Timestamp,
+        }
+    }
 }
 
 #[cfg(feature = "mysql")]
-use self::schema::counts;
+use self::schema::{counter_history, counts};
 
 #[cfg(feature = "mysql")]
 impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
@@ -133,4 +276,134 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
             },
         }
     }
+
+    fn get_counts(&self) -> Result<Vec<(String, i64)>, CounterError> {
+        use self::counts::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(counts::table
+            .select((columns::name, columns::count))
+            .order(columns::name.asc())
+            .load(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn set_count(&mut self, name: &str, count: i64) -> Result<(), CounterError> {
+        use self::counts::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        diesel::replace_into(counts::table)
+            .values((columns::name.eq(name), columns::count.eq(count)))
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn delete_count(&mut self, name: &str) -> Result<(), CounterError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        match diesel::delete(counts::table.find(name)).execute(conn) {
+            Ok(0) => Err(ErrorKind::NotFound)?,
+            Ok(_) => (),
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+
+        diesel::delete(counter_history::table.filter(counter_history::columns::name.eq(name)))
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn get_top(&self, namespace: &str, limit: usize) -> Result<Vec<(String, i64)>, CounterError> {
+        use self::counts::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(namespaced(namespace)
+            .select((columns::name, columns::count))
+            .order((columns::count.desc(), columns::name.asc()))
+            .limit(limit as i64)
+            .load(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn get_bottom(
+        &self,
+        namespace: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, CounterError> {
+        use self::counts::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(namespaced(namespace)
+            .select((columns::name, columns::count))
+            .order((columns::count.asc(), columns::name.asc()))
+            .limit(limit as i64)
+            .load(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn get_rank(&self, namespace: &str, name: &str) -> Result<(i64, i64), CounterError> {
+        use self::counts::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        let count: i64 = match counts::table.find(name).select(columns::count).first(conn) {
+            Ok(count) => count,
+            Err(diesel::result::Error::NotFound) => Err(ErrorKind::NotFound)?,
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        };
+
+        let higher: i64 = namespaced(namespace)
+            .filter(columns::count.gt(count))
+            .count()
+            .get_result(conn)
+            .context(ErrorKind::MysqlError)?;
+        let total: i64 = namespaced(namespace)
+            .count()
+            .get_result(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok((higher + 1, total))
+    }
+
+    fn insert_change(&mut self, change: &NewChange) -> Result<(), CounterError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        diesel::insert_into(counter_history::table)
+            .values(change)
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn get_reasons(&self, name: &str, limit: usize) -> Result<Vec<Change>, CounterError> {
+        use self::counter_history::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(counter_history::table
+            .filter(columns::name.eq(name))
+            .filter(columns::reason.ne(""))
+            .order(columns::id.desc())
+            .limit(limit as i64)
+            .load::<Change>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+}
+
+/// Selects the counters of a namespace, see `in_namespace`.
+#[cfg(feature = "mysql")]
+fn namespaced<'a>(namespace: &str) -> counts::BoxedQuery<'a, diesel::mysql::Mysql> {
+    use self::counts::columns;
+
+    if namespace.is_empty() {
+        counts::table
+            .into_boxed()
+            .filter(columns::name.not_like("% %"))
+    } else {
+        counts::table
+            .into_boxed()
+            .filter(columns::name.like(format!("{} %", escape_like(namespace))))
+    }
 }
@@ -1,10 +1,16 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use antidote::RwLock;
+use antidote::{Mutex, RwLock};
+use chrono::NaiveDateTime;
 use irc::client::prelude::*;
+use time;
 
 use crate::plugin::*;
+use crate::utils::{irc_lowercase, matches_mask};
 use crate::FrippyClient;
 pub mod database;
 use self::database::Database;
@@ -16,42 +22,285 @@ use failure::ResultExt;
 
 use frippy_derive::PluginName;
 
+/// Counters shown by `counter top` and `counter bottom`.
+const LEADERBOARD_SIZE: usize = 5;
+/// Reasons shown by `counter why`.
+const REASON_COUNT: usize = 3;
+/// Characters of a reason which are stored.
+const MAX_REASON_LENGTH: usize = 255;
+
+/// Restrictions on how counters can be changed.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    /// Keep separate counters for every channel
+    pub per_channel: bool,
+    /// Time a user has to wait before changing the same counter again
+    pub cooldown: Duration,
+    /// Names which are never counted, like the "c" in "c++"
+    pub ignored: HashSet<String>,
+}
+
 #[derive(PluginName)]
 pub struct Counter<T: Database, C: Client> {
-    counts: RwLock<T>,
+    counts: Arc<RwLock<T>>,
+    rules: Rules,
+    /// When a user last changed a counter
+    last_changes: Mutex<HashMap<(String, String), Instant>>,
     phantom: PhantomData<C>,
 }
 
+/// Splits a message like "name++ for a reason" into the name,
+/// the operator and the optional reason.
+fn parse(content: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = content.splitn(2, ' ');
+    let change = parts.next().unwrap_or("");
+    let reason = parts.next().unwrap_or("").trim();
+
+    if change.len() <= 2 || !change.is_char_boundary(change.len() - 2) {
+        return None;
+    }
+
+    // Only lines which look like an explanation count,
+    // "c++ is hard" should not change anything
+    if !reason.is_empty() && !reason.starts_with("for ") && !reason.starts_with("because ") {
+        return None;
+    }
+
+    let (name, end) = change.split_at(change.len() - 2);
+    match end {
+        "++" | "--" => Some((name, end, reason)),
+        "==" if reason.is_empty() => Some((name, end, reason)),
+        _ => None,
+    }
+}
+
+fn get_time() -> NaiveDateTime {
+    let tm = time::now().to_timespec();
+    NaiveDateTime::from_timestamp_opt(tm.sec, 0u32).unwrap()
+}
+
 impl<T: Database, C: Client> Counter<T, C> {
-    pub fn new(db: T) -> Self {
+    pub fn new(db: T, rules: Rules) -> Self {
+        let ignored = rules.ignored.iter().map(|n| irc_lowercase(n)).collect();
+
         Self {
-            counts: RwLock::new(db),
+            counts: Arc::new(RwLock::new(db)),
+            rules: Rules { ignored, ..rules },
+            last_changes: Mutex::new(HashMap::new()),
             phantom: PhantomData,
         }
     }
 
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.counts)
+    }
+
     fn get(&self, name: &str) -> Result<String, CounterError> {
         self.counts.read().get_count(name).map(|c| c.to_string())
     }
 
-    fn add(&self, name: &str) -> Result<String, CounterError> {
-        self.counts.write().add(name).map(|c| c.to_string())
+    /// Changes a counter by `delta` and remembers who did it and why.
+    fn change(
+        &self,
+        name: &str,
+        author: &str,
+        delta: i64,
+        reason: &str,
+    ) -> Result<String, CounterError> {
+        let mut counts = self.counts.write();
+        let count = if delta > 0 {
+            counts.add(name)?
+        } else {
+            counts.subtract(name)?
+        };
+
+        let reason = reason.chars().take(MAX_REASON_LENGTH).collect::<String>();
+        counts.insert_change(&database::NewChange {
+            name,
+            author,
+            delta,
+            reason: &reason,
+            time: get_time(),
+        })?;
+
+        Ok(count.to_string())
+    }
+
+    /// Returns the namespace of the counters used in `channel`.
+    fn namespace(&self, channel: &str) -> String {
+        if self.rules.per_channel && (channel.starts_with('#') || channel.starts_with('&')) {
+            channel.to_lowercase()
+        } else {
+            String::new()
+        }
+    }
+
+    /// Returns the name a counter is stored as.
+    fn key(&self, channel: &str, name: &str) -> String {
+        let namespace = self.namespace(channel);
+        if namespace.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{} {}", namespace, name)
+        }
+    }
+
+    fn is_ignored(&self, name: &str) -> bool {
+        self.rules.ignored.contains(&irc_lowercase(name))
+    }
+
+    /// Returns false if `nick` changed the counter too recently.
+    fn check_cooldown(&self, nick: &str, key: &str) -> bool {
+        if self.rules.cooldown == Duration::from_secs(0) {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut last_changes = self.last_changes.lock();
+        let cooldown = self.rules.cooldown;
+        last_changes.retain(|_, &mut time| now.duration_since(time) < cooldown);
+
+        let entry = (irc_lowercase(nick), key.to_owned());
+        if last_changes.contains_key(&entry) {
+            false
+        } else {
+            last_changes.insert(entry, now);
+            true
+        }
+    }
+
+    fn list(counts: Vec<(String, i64)>) -> String {
+        if counts.is_empty() {
+            return String::from("There are no counters yet");
+        }
+
+        counts
+            .into_iter()
+            .map(|(name, count)| {
+                // Hide the namespace of channel counters
+                let name = name.rsplit(' ').next().unwrap_or("");
+                format!("{}: {}", name, count)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn top(&self, command: &PluginCommand) -> Result<String, CounterError> {
+        let namespace = self.namespace(&command.target);
+        Ok(Self::list(
+            self.counts.read().get_top(&namespace, LEADERBOARD_SIZE)?,
+        ))
+    }
+
+    fn bottom(&self, command: &PluginCommand) -> Result<String, CounterError> {
+        let namespace = self.namespace(&command.target);
+        Ok(Self::list(
+            self.counts
+                .read()
+                .get_bottom(&namespace, LEADERBOARD_SIZE)?,
+        ))
+    }
+
+    fn get_command(&self, command: &PluginCommand) -> Result<String, CounterError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = &command.tokens[0];
+        let count = self
+            .counts
+            .read()
+            .get_count(&self.key(&command.target, name))?;
+
+        Ok(format!("{}: {}", name, count))
+    }
+
+    fn reset(&self, client: &C, command: &PluginCommand) -> Result<String, CounterError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        // Nicks can be taken by anybody so owners are identified by their hostmask
+        let is_owner = match (&client.config().owners, &command.hostmask) {
+            (Some(owners), Some(hostmask)) => owners
+                .iter()
+                .any(|owner| owner.contains('!') && matches_mask(owner, hostmask)),
+            _ => false,
+        };
+        if !is_owner {
+            Err(ErrorKind::NotOwner)?;
+        }
+
+        let name = &command.tokens[0];
+        self.counts
+            .write()
+            .delete_count(&self.key(&command.target, name))?;
+
+        Ok(format!("Reset {}", name))
+    }
+
+    fn rank(&self, command: &PluginCommand) -> Result<String, CounterError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = &command.tokens[0];
+        let key = self.key(&command.target, name);
+        let counts = self.counts.read();
+        let count = counts.get_count(&key)?;
+        let (rank, total) = counts.get_rank(&self.namespace(&command.target), &key)?;
+
+        Ok(format!(
+            "{} is ranked {} of {} with {}",
+            name, rank, total, count
+        ))
+    }
+
+    fn why(&self, command: &PluginCommand) -> Result<String, CounterError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let name = &command.tokens[0];
+        let changes = self
+            .counts
+            .read()
+            .get_reasons(&self.key(&command.target, name), REASON_COUNT)?;
+
+        if changes.is_empty() {
+            return Ok(format!("Nobody said why {} changed", name));
+        }
+
+        let reasons = changes
+            .iter()
+            .map(|c| format!("{} {:+} {}", c.author, c.delta, c.reason))
+            .collect::<Vec<_>>();
+
+        Ok(format!("{}: {}", name, reasons.join(", ")))
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"counter help\" for help."
     }
 
-    fn subtract(&self, name: &str) -> Result<String, CounterError> {
-        self.counts.write().subtract(name).map(|c| c.to_string())
+    fn help(&self) -> &str {
+        "usage: counter <subcommand>\r\n\
+         subcommands: top, bottom, get <name>, reset <name>, rank <name>, why <name>, help\r\n\
+         Counters are changed by sending \"name++\" or \"name-- for a reason\" \
+         and shown with \"name==\"."
     }
 }
 
 impl<T: Database, C: FrippyClient> Plugin for Counter<T, C> {
     type Client = C;
     fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
-        if let Command::PRIVMSG(_, content) = message.command.clone() {
-            if content.contains(' ') || !content.is_char_boundary(content.len() - 2) {
-                return ExecutionStatus::Done;
-            }
-            if ["++", "--", "=="].contains(&&content[content.len() - 2..]) {
-                return ExecutionStatus::RequiresThread;
+        if let Command::PRIVMSG(_, ref content) = message.command {
+            if let Some((name, _, _)) = parse(content) {
+                if !self.is_ignored(name) {
+                    return ExecutionStatus::RequiresThread;
+                }
             }
         }
 
@@ -63,31 +312,89 @@ impl<T: Database, C: FrippyClient> Plugin for Counter<T, C> {
         client: &Self::Client,
         message: &Message,
     ) -> Result<(), FrippyError> {
-        if let Command::PRIVMSG(_, content) = message.command.clone() {
-            let (name, end) = content.split_at(content.len() - 2);
+        if let Command::PRIVMSG(ref target, ref content) = message.command {
+            let (name, end, reason) = parse(content).expect("execute checks this already");
+            let source = message.source_nickname().unwrap_or("");
+            let response_target = message.response_target().unwrap_or("");
+            let key = self.key(target, name);
+
+            if end != "==" {
+                if irc_lowercase(name) == irc_lowercase(source) {
+                    client
+                        .send_privmsg(response_target, "You can't change your own counter.")
+                        .context(FrippyErrorKind::Connection)?;
+                    return Ok(());
+                }
+
+                if !self.check_cooldown(source, &key) {
+                    client
+                        .send_notice(
+                            source,
+                            format!("You have to wait before changing {} again.", name),
+                        )
+                        .context(FrippyErrorKind::Connection)?;
+                    return Ok(());
+                }
+            }
+
             let count = match end {
-                "++" => self.add(name),
-                "--" => self.subtract(name),
-                "==" => self.get(name),
+                "++" => self.change(&key, source, 1, reason),
+                "--" => self.change(&key, source, -1, reason),
+                "==" => self.get(&key),
                 _ => unreachable!("execute checks this already"),
             }
             .context(FrippyErrorKind::Counter)?;
 
             client
-                .send_privmsg(message.response_target().unwrap_or(""), count)
+                .send_privmsg(response_target, count)
                 .context(FrippyErrorKind::Connection)?;
         }
 
         Ok(())
     }
 
-    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
-        client
-            .send_privmsg(
-                command.target,
-                "This Plugin does not implement any commands.",
-            )
-            .context(FrippyErrorKind::Connection)?;
+    fn command(
+        &self,
+        client: &Self::Client,
+        mut command: PluginCommand,
+    ) -> Result<(), FrippyError> {
+        if command.tokens.is_empty() {
+            client
+                .send_privmsg(&command.target, self.invalid_command())
+                .context(FrippyErrorKind::Connection)?;
+
+            return Ok(());
+        }
+
+        let sub_command = command.tokens.remove(0);
+        let result = match sub_command.as_ref() {
+            "top" => self.top(&command),
+            "bottom" => self.bottom(&command),
+            "get" => self.get_command(&command),
+            "reset" => self.reset(client, &command),
+            "rank" => self.rank(&command),
+            "why" => self.why(&command),
+            "help" => Ok(self.help().to_owned()),
+            _ => Err(ErrorKind::InvalidCommand.into()),
+        };
+
+        match result {
+            Ok(msg) => {
+                client
+                    .send_privmsg(&command.target, &msg)
+                    .context(FrippyErrorKind::Connection)?;
+            }
+            Err(e) => {
+                let message = match e.kind() {
+                    ErrorKind::InvalidCommand => self.invalid_command().to_owned(),
+                    _ => e.to_string(),
+                };
+                client
+                    .send_privmsg(&command.target, &message)
+                    .context(FrippyErrorKind::Connection)?;
+                Err(e).context(FrippyErrorKind::Counter)?
+            }
+        }
 
         Ok(())
     }
@@ -97,6 +404,21 @@ impl<T: Database, C: FrippyClient> Plugin for Counter<T, C> {
             "Evaluation of commands is not implemented for Factoid at this time",
         ))
     }
+
+    fn inspect(&self) -> String {
+        let cooldown = self.rules.cooldown;
+        let last_changes = self.last_changes.lock();
+        let waiting = last_changes
+            .values()
+            .filter(|time| time.elapsed() < cooldown)
+            .count();
+
+        format!(
+            "{} recent changes remembered, {} of them still cooling down",
+            last_changes.len(),
+            waiting
+        )
+    }
 }
 
 impl<T: Database, C: FrippyClient> fmt::Debug for Counter<T, C> {
@@ -112,6 +434,14 @@ pub mod error {
     #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
     #[error = "CounterError"]
     pub enum ErrorKind {
+        /// Invalid command error
+        #[fail(display = "Invalid Command")]
+        InvalidCommand,
+
+        /// Missing permissions error
+        #[fail(display = "Only owners of the bot can do that")]
+        NotOwner,
+
         /// MySQL error
         #[fail(display = "Failed to execute MySQL Query")]
         MysqlError,
@@ -119,5 +449,9 @@ pub mod error {
         /// No connection error
         #[fail(display = "No connection to the database")]
         NoConnection,
+
+        /// Not found error
+        #[fail(display = "Counter was not found")]
+        NotFound,
     }
 }
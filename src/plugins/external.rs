@@ -0,0 +1,472 @@
+//! Runs plugins as separate processes which communicate with frippy
+//! using line-delimited JSON-RPC 2.0 over stdin and stdout.
+//!
+//! Every message frippy receives is sent to the process as a `message`
+//! notification. Commands and evaluations are sent as `command` and
+//! `evaluate` requests which the process has to answer before the timeout
+//! runs out. At any time the process may send `privmsg`, `notice` or `raw`
+//! notifications which frippy forwards to IRC.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::process::{Child, ChildStdin, ChildStdout, Command as ProcessCommand, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use antidote::Mutex;
+use irc::client::prelude::*;
+use serde_json::{json, Value};
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::{format_err, Fail, ResultExt};
+use log::{debug, error, info, warn};
+
+/// Processes which ran for less than this are restarted with an increasing delay.
+const MIN_UPTIME: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+pub struct External<C> {
+    supervisor: Arc<Supervisor>,
+    launched: AtomicBool,
+    phantom: PhantomData<C>,
+}
+
+struct Process {
+    child: Child,
+    lines: Sender<String>,
+    started: Instant,
+}
+
+struct Supervisor {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    process: Mutex<Option<Process>>,
+    pending: Mutex<HashMap<u64, Sender<Result<Value, String>>>>,
+    next_id: AtomicUsize,
+    backoff: Mutex<Duration>,
+}
+
+impl<C: FrippyClient + 'static> External<C> {
+    /// Creates a plugin called `name` which runs `command_line` once
+    /// the bot receives its first message.
+    /// Requests which take longer than `timeout` to answer cause a restart.
+    pub fn new(name: &str, command_line: &str, timeout: Duration) -> Self {
+        let mut parts = command_line.split_whitespace().map(ToOwned::to_owned);
+
+        External {
+            supervisor: Arc::new(Supervisor {
+                name: name.to_owned(),
+                program: parts.next().unwrap_or_default(),
+                args: parts.collect(),
+                timeout,
+                process: Mutex::new(None),
+                pending: Mutex::new(HashMap::new()),
+                next_id: AtomicUsize::new(0),
+                backoff: Mutex::new(Duration::from_secs(1)),
+            }),
+            launched: AtomicBool::new(false),
+            phantom: PhantomData,
+        }
+    }
+
+    fn launch(&self, client: &C) {
+        if self.launched.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Err(e) = self.supervisor.start(client) {
+            error!("Failed to start {}: {}", self.supervisor.name, e);
+
+            let supervisor = Arc::clone(&self.supervisor);
+            let client = client.clone();
+            thread::spawn(move || supervisor.relaunch(&client));
+        }
+    }
+
+    fn command_params(command: &PluginCommand) -> Value {
+        json!({
+            "source": command.source,
+            "target": command.target,
+            "tokens": command.tokens,
+        })
+    }
+}
+
+impl Supervisor {
+    fn start<C: FrippyClient + 'static>(self: &Arc<Self>, client: &C) -> Result<(), ExternalError> {
+        let mut process = self.process.lock();
+        if process.is_some() {
+            return Ok(());
+        }
+
+        let mut child = ProcessCommand::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .context(ErrorKind::Spawn)?;
+
+        let stdin = child.stdin.take().expect("stdin should be piped");
+        let stdout = child.stdout.take().expect("stdout should be piped");
+        let (lines, receiver) = mpsc::channel();
+
+        thread::Builder::new()
+            .name(format!("{} writer", self.name))
+            .spawn(move || write_lines(stdin, receiver))
+            .context(ErrorKind::Spawn)?;
+
+        let supervisor = Arc::clone(self);
+        let client = client.clone();
+        thread::Builder::new()
+            .name(format!("{} reader", self.name))
+            .spawn(move || supervisor.read_lines(&client, stdout))
+            .context(ErrorKind::Spawn)?;
+
+        *process = Some(Process {
+            child,
+            lines,
+            started: Instant::now(),
+        });
+
+        info!("Started external plugin {}", self.name);
+
+        Ok(())
+    }
+
+    /// Keeps trying to start the process with an increasing delay.
+    fn relaunch<C: FrippyClient + 'static>(self: &Arc<Self>, client: &C) {
+        loop {
+            let backoff = *self.backoff.lock();
+            thread::sleep(backoff);
+
+            match self.start(client) {
+                Ok(()) => return,
+                Err(e) => {
+                    error!("Failed to restart {}: {}", self.name, e);
+                    *self.backoff.lock() = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    fn read_lines<C: FrippyClient + 'static>(self: Arc<Self>, client: &C, stdout: ChildStdout) {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Failed to read from {}: {}", self.name, e);
+                    break;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.handle_line(client, &line) {
+                error!("{} sent {:?}: {}", self.name, line, e);
+            }
+        }
+
+        // The process closed stdout, so it crashed, exited or was killed
+        let uptime = match self.process.lock().take() {
+            Some(mut process) => {
+                let _ = process.child.kill();
+                match process.child.wait() {
+                    Ok(status) => warn!("External plugin {} exited with {}", self.name, status),
+                    Err(e) => error!("Failed to wait for {}: {}", self.name, e),
+                }
+
+                process.started.elapsed()
+            }
+            None => return,
+        };
+
+        for (_, sender) in self.pending.lock().drain() {
+            let _ = sender.send(Err(String::from("The plugin process exited")));
+        }
+
+        {
+            let mut backoff = self.backoff.lock();
+            *backoff = if uptime < MIN_UPTIME {
+                (*backoff * 2).min(MAX_BACKOFF)
+            } else {
+                Duration::from_secs(1)
+            };
+        }
+
+        self.relaunch(client);
+    }
+
+    fn handle_line<C: FrippyClient>(&self, client: &C, line: &str) -> Result<(), ExternalError> {
+        let value: Value = serde_json::from_str(line).context(ErrorKind::InvalidMessage)?;
+
+        let method = match value.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            None => return self.handle_response(&value),
+        };
+
+        let params = &value["params"];
+        match method {
+            "privmsg" => client
+                .send_privmsg(str_param(params, "target")?, str_param(params, "message")?)
+                .context(ErrorKind::Connection)?,
+            "notice" => client
+                .send_notice(str_param(params, "target")?, str_param(params, "message")?)
+                .context(ErrorKind::Connection)?,
+            "raw" => {
+                let message = str_param(params, "line")?
+                    .parse::<Message>()
+                    .context(ErrorKind::InvalidMessage)?;
+                client.send(message).context(ErrorKind::Connection)?
+            }
+            _ => Err(ErrorKind::UnknownMethod)?,
+        }
+
+        Ok(())
+    }
+
+    fn handle_response(&self, value: &Value) -> Result<(), ExternalError> {
+        let id = value
+            .get("id")
+            .and_then(Value::as_u64)
+            .ok_or(ErrorKind::InvalidMessage)?;
+
+        let result = match value.get("error") {
+            Some(error) => Err(error
+                .get("message")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| error.to_string())),
+            None => Ok(value.get("result").cloned().unwrap_or(Value::Null)),
+        };
+
+        match self.pending.lock().remove(&id) {
+            Some(sender) => {
+                let _ = sender.send(result);
+            }
+            None => debug!("{} answered unknown or expired request {}", self.name, id),
+        }
+
+        Ok(())
+    }
+
+    fn send_line(&self, line: String) -> Result<(), ExternalError> {
+        let process = self.process.lock();
+        let process = process.as_ref().ok_or(ErrorKind::NotRunning)?;
+
+        process
+            .lines
+            .send(line)
+            .map_err(|_| ErrorKind::NotRunning.into())
+    }
+
+    fn notify(&self, method: &str, params: Value) -> Result<(), ExternalError> {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+
+        self.send_line(notification.to_string())
+    }
+
+    fn request(&self, method: &str, params: Value) -> Result<Value, ExternalError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        let (sender, receiver) = mpsc::channel();
+        self.pending.lock().insert(id, sender);
+
+        if let Err(e) = self.send_line(request.to_string()) {
+            self.pending.lock().remove(&id);
+            return Err(e);
+        }
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(message)) => Err(format_err!("{}", message)).context(ErrorKind::Remote)?,
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending.lock().remove(&id);
+
+                // Killing the process makes the reader thread restart it
+                warn!("{} timed out - restarting it", self.name);
+                if let Some(process) = self.process.lock().as_mut() {
+                    let _ = process.child.kill();
+                }
+
+                Err(ErrorKind::Timeout)?
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(ErrorKind::NotRunning)?,
+        }
+    }
+}
+
+fn write_lines(mut stdin: ChildStdin, lines: Receiver<String>) {
+    for line in lines {
+        if writeln!(stdin, "{}", line)
+            .and_then(|_| stdin.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn str_param<'a>(params: &'a Value, key: &str) -> Result<&'a str, ExternalError> {
+    Ok(params
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or(ErrorKind::InvalidMessage)?)
+}
+
+/// Converts a result of a request into text which can be sent to IRC.
+fn result_text(result: &Value) -> Option<String> {
+    match result {
+        Value::Null => None,
+        Value::String(s) => Some(s.to_owned()),
+        Value::Array(lines) => Some(
+            lines
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\r\n"),
+        ),
+        v => Some(v.to_string()),
+    }
+}
+
+fn error_text(e: &ExternalError) -> String {
+    match e.kind() {
+        ErrorKind::Remote => e.cause().unwrap().to_string(),
+        _ => e.to_string(),
+    }
+}
+
+impl<C> PluginName for External<C> {
+    fn name(&self) -> &str {
+        &self.supervisor.name
+    }
+}
+
+impl<C: FrippyClient + 'static> Plugin for External<C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        self.launch(client);
+
+        let mut params = json!({
+            "raw": message.to_string().trim_end(),
+            "source": message.source_nickname(),
+            "target": message.response_target(),
+        });
+        if let Command::PRIVMSG(_, ref content) = message.command {
+            params["content"] = Value::from(content.as_str());
+        }
+
+        match self.supervisor.notify("message", params) {
+            Ok(()) => ExecutionStatus::Done,
+            Err(ref e) if e.kind() == ErrorKind::NotRunning => {
+                debug!("{} is not running - dropping message", self.name());
+                ExecutionStatus::Done
+            }
+            Err(e) => ExecutionStatus::Err(e.context(FrippyErrorKind::External).into()),
+        }
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("External should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        self.launch(client);
+
+        let params = Self::command_params(&command);
+        match self.supervisor.request("command", params) {
+            Ok(result) => {
+                if let Some(text) = result_text(&result) {
+                    client
+                        .send_privmsg(&command.target, text)
+                        .context(FrippyErrorKind::Connection)?;
+                }
+            }
+            Err(e) => {
+                client
+                    .send_privmsg(&command.target, error_text(&e))
+                    .context(FrippyErrorKind::Connection)?;
+                Err(e).context(FrippyErrorKind::External)?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, client: &Self::Client, command: PluginCommand) -> Result<String, String> {
+        self.launch(client);
+
+        let params = Self::command_params(&command);
+        match self.supervisor.request("evaluate", params) {
+            Ok(result) => Ok(result_text(&result).unwrap_or_default()),
+            Err(e) => Err(error_text(&e)),
+        }
+    }
+}
+
+impl<C> fmt::Debug for External<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "External {{ name: {:?} }}", self.supervisor.name)
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "ExternalError"]
+    pub enum ErrorKind {
+        /// Spawn error
+        #[fail(display = "Failed to start the plugin process")]
+        Spawn,
+
+        /// Not running error
+        #[fail(display = "The plugin process is not running")]
+        NotRunning,
+
+        /// Timeout error
+        #[fail(display = "The plugin process took too long to respond")]
+        Timeout,
+
+        /// Invalid message error
+        #[fail(display = "The plugin process sent an invalid message")]
+        InvalidMessage,
+
+        /// Unknown method error
+        #[fail(display = "The plugin process called an unknown method")]
+        UnknownMethod,
+
+        /// Remote error
+        #[fail(display = "The plugin process returned an error")]
+        Remote,
+
+        /// Connection error
+        #[fail(display = "Failed to send a message to IRC")]
+        Connection,
+    }
+}
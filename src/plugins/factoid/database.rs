@@ -13,6 +13,9 @@ use r2d2::Pool;
 #[cfg(feature = "mysql")]
 use r2d2_diesel::ConnectionManager;
 
+#[cfg(feature = "mysql")]
+use crate::utils::escape_like;
+
 use chrono::NaiveDateTime;
 
 use super::error::*;
@@ -42,6 +45,7 @@ pub trait Database: Send + Sync {
     fn get_factoid(&self, name: &str, idx: i32) -> Result<Factoid, FactoidError>;
     fn delete_factoid(&mut self, name: &str, idx: i32) -> Result<(), FactoidError>;
     fn count_factoids(&self, name: &str) -> Result<i32, FactoidError>;
+    fn search_factoids(&self, query: &str) -> Result<Vec<Factoid>, FactoidError>;
 }
 
 // HashMap
@@ -79,6 +83,20 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<(String, i3
     fn count_factoids(&self, name: &str) -> Result<i32, FactoidError> {
         Ok(self.iter().filter(|&((n, _), _)| n == name).count() as i32)
     }
+
+    fn search_factoids(&self, query: &str) -> Result<Vec<Factoid>, FactoidError> {
+        let query = query.to_lowercase();
+        let mut factoids = self
+            .values()
+            .filter(|f| {
+                f.name.to_lowercase().contains(&query) || f.content.to_lowercase().contains(&query)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        factoids.sort_by(|a, b| a.name.cmp(&b.name).then(a.idx.cmp(&b.idx)));
+        Ok(factoids)
+    }
 }
 
 // Diesel automatically defines the factoids module as public.
@@ -156,4 +174,21 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
             Err(e) => Err(e).context(ErrorKind::MysqlError)?,
         }
     }
+
+    fn search_factoids(&self, query: &str) -> Result<Vec<Factoid>, FactoidError> {
+        use self::factoids::columns;
+
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        let pattern = format!("%{}%", escape_like(query));
+
+        Ok(factoids::table
+            .filter(
+                columns::name
+                    .like(&pattern)
+                    .or(columns::content.like(&pattern)),
+            )
+            .order((columns::name.asc(), columns::idx.asc()))
+            .load::<Factoid>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
 }
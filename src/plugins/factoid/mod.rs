@@ -1,6 +1,7 @@
 use std::fmt;
 use std::marker::PhantomData;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
@@ -13,6 +14,7 @@ use rlua::HookTriggers;
 use chrono::NaiveDateTime;
 use time;
 
+use crate::metrics;
 use crate::plugin::*;
 use crate::FrippyClient;
 pub mod database;
@@ -33,18 +35,23 @@ static LUA_SANDBOX: &str = include_str!("sandbox.lua");
 
 #[derive(PluginName)]
 pub struct Factoid<T: Database, C: Client> {
-    factoids: RwLock<T>,
+    factoids: Arc<RwLock<T>>,
     phantom: PhantomData<C>,
 }
 
 impl<T: Database, C: Client> Factoid<T, C> {
     pub fn new(db: T) -> Self {
         Factoid {
-            factoids: RwLock::new(db),
+            factoids: Arc::new(RwLock::new(db)),
             phantom: PhantomData,
         }
     }
 
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.factoids)
+    }
+
     fn create_factoid(
         &self,
         name: &str,
@@ -80,7 +87,11 @@ impl<T: Database, C: Client> Factoid<T, C> {
         self.create_factoid(&name, &content, &command.source)
     }
 
-    fn add_from_url(&self, command: &mut PluginCommand) -> Result<&str, FactoidError> {
+    fn add_from_url(
+        &self,
+        network: &str,
+        command: &mut PluginCommand,
+    ) -> Result<&str, FactoidError> {
         if command.tokens.len() < 2 {
             Err(ErrorKind::InvalidCommand)?;
         }
@@ -89,6 +100,7 @@ impl<T: Database, C: Client> Factoid<T, C> {
         let url = &command.tokens[0];
         let content = Url::from(url.as_ref())
             .max_kib(1024)
+            .network(network)
             .request()
             .context(ErrorKind::Download)?;
 
@@ -171,7 +183,7 @@ impl<T: Database, C: Client> Factoid<T, C> {
         }
     }
 
-    fn exec(&self, mut command: PluginCommand) -> Result<String, FactoidError> {
+    fn exec(&self, network: &str, mut command: PluginCommand) -> Result<String, FactoidError> {
         if command.tokens.is_empty() {
             Err(ErrorKind::InvalidIndex)?
         } else {
@@ -186,16 +198,22 @@ impl<T: Database, C: Client> Factoid<T, C> {
                 if content.starts_with('>') {
                     content
                 } else {
-                    match self.run_lua(&name, &content, &command) {
+                    match self.run_lua(network, &name, &content, &command) {
                         Ok(v) => v,
                         Err(e) => match e {
                             LuaError::CallbackError { cause, .. } => match *cause {
                                 LuaError::MemoryError(_) => {
+                                    metrics::LUA_MEMORY_ERRORS
+                                        .with_label_values(&[network])
+                                        .inc();
                                     String::from("memory error: Factoid used over 1 MiB of ram")
                                 }
                                 _ => cause.to_string(),
                             },
                             LuaError::MemoryError(_) => {
+                                metrics::LUA_MEMORY_ERRORS
+                                    .with_label_values(&[network])
+                                    .inc();
                                 String::from("memory error: Factoid used over 1 MiB of ram")
                             }
                             _ => e.to_string(),
@@ -211,7 +229,15 @@ impl<T: Database, C: Client> Factoid<T, C> {
         }
     }
 
-    fn run_lua(&self, name: &str, code: &str, command: &PluginCommand) -> Result<String, LuaError> {
+    fn run_lua(
+        &self,
+        network: &str,
+        name: &str,
+        code: &str,
+        command: &PluginCommand,
+    ) -> Result<String, LuaError> {
+        metrics::LUA_EXECUTIONS.with_label_values(&[network]).inc();
+
         let args = command
             .tokens
             .iter()
@@ -224,6 +250,9 @@ impl<T: Database, C: Client> Factoid<T, C> {
         lua.set_memory_limit(Some(1024 * 1024));
 
         let start = Instant::now();
+        let download_network = network.to_owned();
+        let network = network.to_owned();
+        let timed_out = AtomicBool::new(false);
         // Check if the factoid timed out
         lua.set_hook(
             HookTriggers {
@@ -232,6 +261,10 @@ impl<T: Database, C: Client> Factoid<T, C> {
             },
             move |_, _| {
                 if Instant::now() - start > Duration::from_secs(30) {
+                    if !timed_out.swap(true, Ordering::Relaxed) {
+                        metrics::LUA_TIMEOUTS.with_label_values(&[&network]).inc();
+                    }
+
                     return Err(LuaError::ExternalError(Arc::new(
                         format_err!("Factoid timed out after 30 seconds").compat(),
                     )));
@@ -250,7 +283,7 @@ impl<T: Database, C: Client> Factoid<T, C> {
             globals.set("factoid", code)?;
             globals.set(
                 "download",
-                ctx.create_function(|ctx, url| download(&ctx, url))?,
+                ctx.create_function(move |ctx, url| download(&ctx, &download_network, url))?,
             )?;
             globals.set(
                 "json_decode",
@@ -304,11 +337,12 @@ impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
 
             let c = PluginCommand {
                 source: message.source_nickname().unwrap().to_owned(),
+                hostmask: message.prefix.clone(),
                 target: message.response_target().unwrap().to_owned(),
                 tokens: t,
             };
 
-            if let Ok(f) = self.exec(c) {
+            if let Ok(f) = self.exec(&metrics::network(client), c) {
                 client
                     .send_privmsg(message.response_target().unwrap(), f)
                     .context(FrippyErrorKind::Connection)?;
@@ -336,11 +370,13 @@ impl<T: Database, C: FrippyClient> Plugin for Factoid<T, C> {
         let sub_command = command.tokens.remove(0);
         let result = match sub_command.as_ref() {
             "add" => self.add(&mut command).map(|s| s.to_owned()),
-            "fromurl" => self.add_from_url(&mut command).map(|s| s.to_owned()),
+            "fromurl" => self
+                .add_from_url(&metrics::network(client), &mut command)
+                .map(|s| s.to_owned()),
             "remove" => self.remove(&mut command).map(|s| s.to_owned()),
             "get" => self.get(&command),
             "info" => self.info(&command),
-            "exec" => self.exec(command),
+            "exec" => self.exec(&metrics::network(client), command),
             "help" => Ok(self.help().to_owned()),
             _ => Err(ErrorKind::InvalidCommand.into()),
         };
@@ -17,8 +17,8 @@ pub fn sleep(_: &Context, dur: u64) -> Result<(), LuaError> {
     Ok(())
 }
 
-pub fn download(_: &Context, url: String) -> Result<String, LuaError> {
-    let url = Url::from(url).max_kib(1024);
+pub fn download(_: &Context, network: &str, url: String) -> Result<String, LuaError> {
+    let url = Url::from(url).max_kib(1024).network(network);
     match url.request() {
         Ok(v) => Ok(v),
         Err(e) => {
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "mysql")]
+use std::sync::Arc;
+
+#[cfg(feature = "mysql")]
+use diesel::mysql::MysqlConnection;
+#[cfg(feature = "mysql")]
+use diesel::prelude::*;
+#[cfg(feature = "mysql")]
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+#[cfg(feature = "mysql")]
+use failure::ResultExt;
+#[cfg(feature = "mysql")]
+use r2d2::Pool;
+#[cfg(feature = "mysql")]
+use r2d2_diesel::ConnectionManager;
+
+use chrono::NaiveDateTime;
+
+use super::error::*;
+
+#[cfg_attr(feature = "mysql", derive(Queryable))]
+#[derive(Clone, Debug)]
+pub struct Subscription {
+    pub url: String,
+    pub channel: String,
+    pub author: String,
+    pub created: NaiveDateTime,
+}
+
+#[cfg_attr(feature = "mysql", derive(Insertable))]
+#[cfg_attr(feature = "mysql", table_name = "feed_subscriptions")]
+pub struct NewSubscription<'a> {
+    pub url: &'a str,
+    pub channel: &'a str,
+    pub author: &'a str,
+    pub created: NaiveDateTime,
+}
+
+/// Keeps the subscriptions of a feed and the items which were announced.
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    pub subscriptions: Vec<Subscription>,
+    pub items: HashSet<String>,
+}
+
+pub trait Database: Send + Sync {
+    fn insert_subscription(&mut self, subscription: &NewSubscription) -> Result<(), FeedError>;
+    fn delete_subscription(&mut self, url: &str, channel: &str) -> Result<(), FeedError>;
+    fn get_subscriptions(&self) -> Result<Vec<Subscription>, FeedError>;
+    fn insert_item(&mut self, url: &str, item: &str) -> Result<(), FeedError>;
+    fn has_item(&self, url: &str, item: &str) -> Result<bool, FeedError>;
+}
+
+// HashMap
+impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Feed, S> {
+    fn insert_subscription(&mut self, subscription: &NewSubscription) -> Result<(), FeedError> {
+        let feed = self.entry(subscription.url.to_owned()).or_default();
+
+        if feed
+            .subscriptions
+            .iter()
+            .any(|s| s.channel == subscription.channel)
+        {
+            Err(ErrorKind::Duplicate)?;
+        }
+
+        feed.subscriptions.push(Subscription {
+            url: subscription.url.to_owned(),
+            channel: subscription.channel.to_owned(),
+            author: subscription.author.to_owned(),
+            created: subscription.created,
+        });
+
+        Ok(())
+    }
+
+    fn delete_subscription(&mut self, url: &str, channel: &str) -> Result<(), FeedError> {
+        let feed = self.get_mut(url).ok_or(ErrorKind::NotFound)?;
+        let count = feed.subscriptions.len();
+        feed.subscriptions.retain(|s| s.channel != channel);
+
+        if feed.subscriptions.len() == count {
+            Err(ErrorKind::NotFound)?;
+        }
+
+        // Forget the announced items once nobody is subscribed anymore
+        if feed.subscriptions.is_empty() {
+            self.remove(url);
+        }
+
+        Ok(())
+    }
+
+    fn get_subscriptions(&self) -> Result<Vec<Subscription>, FeedError> {
+        Ok(self
+            .values()
+            .flat_map(|f| f.subscriptions.iter().cloned())
+            .collect())
+    }
+
+    fn insert_item(&mut self, url: &str, item: &str) -> Result<(), FeedError> {
+        self.entry(url.to_owned())
+            .or_default()
+            .items
+            .insert(item.to_owned());
+
+        Ok(())
+    }
+
+    fn has_item(&self, url: &str, item: &str) -> Result<bool, FeedError> {
+        Ok(self.get(url).map_or(false, |f| f.items.contains(item)))
+    }
+}
+
+// Diesel automatically defines the tables as public modules.
+// We create a schema module to keep them private.
+#[cfg(feature = "mysql")]
+mod schema {
+    table! {
+        feed_subscriptions (url, channel) {
+            url -> Varchar,
+            channel -> Varchar,
+            author -> Varchar,
+            created -> Timestamp,
+        }
+    }
+
+    table! {
+        feed_items (url, item) {
+            url -> Varchar,
+            item -> Char,
+        }
+    }
+}
+
+#[cfg(feature = "mysql")]
+use self::schema::{feed_items, feed_subscriptions};
+
+#[cfg(feature = "mysql")]
+impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
+    fn insert_subscription(&mut self, subscription: &NewSubscription) -> Result<(), FeedError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        match diesel::insert_into(feed_subscriptions::table)
+            .values(subscription)
+            .execute(conn)
+        {
+            Ok(_) => Ok(()),
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(ErrorKind::Duplicate)?
+            }
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+    }
+
+    fn delete_subscription(&mut self, url: &str, channel: &str) -> Result<(), FeedError> {
+        use self::feed_subscriptions::columns;
+
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        match diesel::delete(feed_subscriptions::table.find((url, channel))).execute(conn) {
+            Ok(0) => Err(ErrorKind::NotFound)?,
+            Ok(_) => (),
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+
+        let remaining: i64 = feed_subscriptions::table
+            .filter(columns::url.eq(url))
+            .count()
+            .get_result(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        // Forget the announced items once nobody is subscribed anymore
+        if remaining == 0 {
+            diesel::delete(feed_items::table.filter(feed_items::columns::url.eq(url)))
+                .execute(conn)
+                .context(ErrorKind::MysqlError)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_subscriptions(&self) -> Result<Vec<Subscription>, FeedError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(feed_subscriptions::table
+            .load::<Subscription>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn insert_item(&mut self, url: &str, item: &str) -> Result<(), FeedError> {
+        use self::feed_items::columns;
+
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        diesel::replace_into(feed_items::table)
+            .values((columns::url.eq(url), columns::item.eq(item)))
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn has_item(&self, url: &str, item: &str) -> Result<bool, FeedError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        let count: i64 = feed_items::table
+            .find((url, item))
+            .count()
+            .get_result(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(count > 0)
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use antidote::RwLock;
+use irc::client::data::{AccessLevel, User};
+use irc::client::prelude::*;
+
+use chrono::NaiveDateTime;
+use sha2::{Digest, Sha256};
+use time;
+
+use crate::metrics;
+use crate::plugin::*;
+use crate::utils::Url;
+use crate::FrippyClient;
+
+pub mod database;
+use self::database::Database;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::ResultExt;
+use log::{debug, error};
+
+use frippy_derive::PluginName;
+
+/// Items which are announced per feed and poll at most.
+const MAX_ANNOUNCEMENTS: usize = 3;
+
+fn get_time() -> NaiveDateTime {
+    let tm = time::now().to_timespec();
+    NaiveDateTime::from_timestamp_opt(tm.sec, 0u32).unwrap()
+}
+
+/// Downloads and parses a feed.
+fn fetch(network: &str, url: &str) -> Result<feed_rs::model::Feed, FeedError> {
+    let body = Url::from(url)
+        .max_kib(2048)
+        .timeout(Duration::from_secs(10))
+        .network(network)
+        .request()
+        .context(ErrorKind::Download)?;
+
+    Ok(feed_rs::parser::parse(body.as_bytes()).context(ErrorKind::Parse)?)
+}
+
+/// Returns a key of fixed length which identifies an item of a feed.
+fn item_key(entry: &feed_rs::model::Entry) -> String {
+    hex::encode(Sha256::digest(entry.id.as_bytes()))
+}
+
+/// Puts text from a feed on a single line so it can't send extra IRC commands.
+fn single_line(text: &str) -> String {
+    text.trim().replace(|c| c == '\r' || c == '\n', " ")
+}
+
+/// Announces items which were not seen before to all subscribed channels.
+fn poll<T: Database, C: FrippyClient>(client: &C, db: &RwLock<T>) -> Result<(), FeedError> {
+    let subscriptions = db.read().get_subscriptions()?;
+
+    let mut channels = HashMap::new();
+    for subscription in subscriptions {
+        channels
+            .entry(subscription.url)
+            .or_insert_with(Vec::new)
+            .push(subscription.channel);
+    }
+
+    let network = metrics::network(client);
+    for (url, channels) in channels {
+        let feed = match fetch(&network, &url) {
+            Ok(feed) => feed,
+            Err(e) => {
+                error!("Failed to fetch feed {}: {}", url, e);
+                continue;
+            }
+        };
+
+        let feed_title = feed
+            .title
+            .as_ref()
+            .map(|t| single_line(&t.content))
+            .unwrap_or_else(|| url.clone());
+
+        let mut new_items = Vec::new();
+        for entry in &feed.entries {
+            let key = item_key(entry);
+            if !db.read().has_item(&url, &key)? {
+                new_items.push((key, entry));
+            }
+        }
+
+        // Feeds list their newest items first, only those are announced
+        // if a feed had a lot of changes
+        for (i, (key, entry)) in new_items.into_iter().enumerate() {
+            db.write().insert_item(&url, &key)?;
+
+            if i >= MAX_ANNOUNCEMENTS {
+                continue;
+            }
+
+            let title = entry
+                .title
+                .as_ref()
+                .map(|t| single_line(&t.content))
+                .unwrap_or_default();
+            let link = entry
+                .links
+                .first()
+                .map(|l| single_line(&l.href))
+                .unwrap_or_default();
+            let message = format!("[{}] {} {}", feed_title, title, link);
+
+            for channel in &channels {
+                if let Err(e) = client.send_privmsg(channel, message.trim_end()) {
+                    error!("Failed to announce feed item: {}", e);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run<T: Database, C: FrippyClient>(client: &C, db: Arc<RwLock<T>>, interval: Duration) {
+    loop {
+        if let Err(e) = poll(client, &db) {
+            error!("Failed to poll feeds: {}", e);
+        }
+
+        sleep(interval);
+    }
+}
+
+#[derive(PluginName)]
+pub struct Feeds<T: Database + 'static, C> {
+    feeds: Arc<RwLock<T>>,
+    interval: Duration,
+    is_polling: RwLock<bool>,
+    phantom: PhantomData<C>,
+}
+
+impl<T: Database + 'static, C: FrippyClient> Feeds<T, C> {
+    /// Creates a plugin which checks the subscribed feeds every `interval`.
+    pub fn new(db: T, interval: Duration) -> Self {
+        Feeds {
+            feeds: Arc::new(RwLock::new(db)),
+            interval,
+            is_polling: RwLock::new(false),
+            phantom: PhantomData,
+        }
+    }
+
+    fn is_operator(&self, client: &C, channel: &str, nick: &str) -> bool {
+        let users: Vec<User> = client.list_users(channel).unwrap_or_default();
+
+        users.iter().filter(|u| u.get_nickname() == nick).any(|u| {
+            u.access_levels().iter().any(|level| match level {
+                AccessLevel::Owner | AccessLevel::Admin | AccessLevel::Oper => true,
+                _ => false,
+            })
+        })
+    }
+
+    fn add(&self, client: &C, command: &PluginCommand) -> Result<String, FeedError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        if !self.is_operator(client, &command.target, &command.source) {
+            Err(ErrorKind::NotOperator)?;
+        }
+
+        let url = &command.tokens[0];
+        let feed = fetch(&metrics::network(client), url)?;
+
+        let subscription = database::NewSubscription {
+            url,
+            channel: &command.target,
+            author: &command.source,
+            created: get_time(),
+        };
+
+        let mut feeds = self.feeds.write();
+        feeds.insert_subscription(&subscription)?;
+
+        // Existing items should not be announced
+        for entry in &feed.entries {
+            feeds.insert_item(url, &item_key(entry))?;
+        }
+
+        Ok(format!("Subscribed to {}", url))
+    }
+
+    fn remove(&self, client: &C, command: &PluginCommand) -> Result<String, FeedError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        if !self.is_operator(client, &command.target, &command.source) {
+            Err(ErrorKind::NotOperator)?;
+        }
+
+        let url = &command.tokens[0];
+        self.feeds
+            .write()
+            .delete_subscription(url, &command.target)?;
+
+        Ok(format!("Unsubscribed from {}", url))
+    }
+
+    fn list(&self, command: &PluginCommand) -> Result<String, FeedError> {
+        let urls = self
+            .feeds
+            .read()
+            .get_subscriptions()?
+            .into_iter()
+            .filter(|s| s.channel == command.target)
+            .map(|s| s.url)
+            .collect::<Vec<_>>();
+
+        if urls.is_empty() {
+            Ok(String::from("This channel is not subscribed to any feeds"))
+        } else {
+            Ok(urls.join(", "))
+        }
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"feeds help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "usage: feeds <subcommand>\r\n\
+         subcommands: add <url>, remove <url>, list, help\r\n\
+         Only channel operators can add and remove feeds."
+    }
+}
+
+impl<T: Database + 'static, C: FrippyClient + 'static> Plugin for Feeds<T, C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        if let Command::JOIN(_, _, _) = message.command {
+            let mut is_polling = self.is_polling.write();
+
+            if !*is_polling {
+                let feeds = Arc::clone(&self.feeds);
+                let client = client.clone();
+                let interval = self.interval;
+
+                spawn(move || run(&client, feeds, interval));
+
+                *is_polling = true;
+            }
+        }
+
+        ExecutionStatus::Done
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Feeds should not use frippy's threading")
+    }
+
+    fn command(
+        &self,
+        client: &Self::Client,
+        mut command: PluginCommand,
+    ) -> Result<(), FrippyError> {
+        if command.tokens.is_empty() {
+            client
+                .send_privmsg(&command.target, self.invalid_command())
+                .context(FrippyErrorKind::Connection)?;
+
+            return Ok(());
+        }
+
+        let sub_command = command.tokens.remove(0);
+        debug!("Feeds command: {} {:?}", sub_command, command.tokens);
+
+        let result = if command.target == command.source {
+            Err(ErrorKind::NoChannel.into())
+        } else {
+            match sub_command.as_ref() {
+                "add" => self.add(client, &command),
+                "remove" => self.remove(client, &command),
+                "list" => self.list(&command),
+                "help" => Ok(self.help().to_owned()),
+                _ => Err(ErrorKind::InvalidCommand.into()),
+            }
+        };
+
+        match result {
+            Ok(msg) => {
+                client
+                    .send_privmsg(&command.target, &msg)
+                    .context(FrippyErrorKind::Connection)?;
+            }
+            Err(e) => {
+                let message = e.to_string();
+                client
+                    .send_privmsg(&command.target, &message)
+                    .context(FrippyErrorKind::Connection)?;
+                Err(e).context(FrippyErrorKind::Feeds)?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from(
+            "Evaluation of commands is not implemented for Feeds at this time",
+        ))
+    }
+
+    fn inspect(&self) -> String {
+        let polling = if *self.is_polling.read() {
+            format!("Polling every {} seconds", self.interval.as_secs())
+        } else {
+            String::from("Not polling yet")
+        };
+
+        match self.feeds.read().get_subscriptions() {
+            Ok(subscriptions) => format!("{}, {} subscriptions", polling, subscriptions.len()),
+            Err(e) => format!("{}, {}", polling, e),
+        }
+    }
+}
+
+impl<T: Database, C: FrippyClient> fmt::Debug for Feeds<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Feeds {{ ... }}")
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "FeedError"]
+    pub enum ErrorKind {
+        /// Invalid command error
+        #[fail(display = "Invalid Command")]
+        InvalidCommand,
+
+        /// Command outside of a channel error
+        #[fail(display = "Feeds can only be managed in channels")]
+        NoChannel,
+
+        /// Missing permissions error
+        #[fail(display = "Only channel operators can do that")]
+        NotOperator,
+
+        /// Download error
+        #[fail(display = "Failed to download the feed")]
+        Download,
+
+        /// Parse error
+        #[fail(display = "Failed to parse the feed")]
+        Parse,
+
+        /// Duplicate error
+        #[fail(display = "This channel is already subscribed to that feed")]
+        Duplicate,
+
+        /// Not found error
+        #[fail(display = "This channel is not subscribed to that feed")]
+        NotFound,
+
+        /// MySQL error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "Failed to execute MySQL Query")]
+        MysqlError,
+
+        /// No connection error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "No connection to the database")]
+        NoConnection,
+    }
+}
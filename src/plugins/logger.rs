@@ -0,0 +1,303 @@
+//! Writes channel events into one file per network, channel and day
+//! using the format of irssi.
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use antidote::{Mutex, RwLock};
+use chrono::Local;
+use irc::client::prelude::*;
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::ResultExt;
+use log::error;
+
+use frippy_derive::PluginName;
+
+/// Name of the file in the log directory which stores the users that opted out.
+const OPT_OUT_FILE: &str = "optout";
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(PluginName, Debug)]
+pub struct Logger<C> {
+    dir: PathBuf,
+    excluded: HashSet<String>,
+    opted_out: RwLock<HashSet<String>>,
+    /// The lowercase nicks in each channel, used to log quits and nick changes
+    members: Mutex<HashMap<String, HashSet<String>>>,
+    phantom: PhantomData<C>,
+}
+
+impl<C: FrippyClient> Logger<C> {
+    /// Creates a logger which writes into `dir` and ignores the `excluded` channels.
+    pub fn new<P: AsRef<Path>>(dir: P, excluded: &[&str]) -> Self {
+        let dir = dir.as_ref().to_owned();
+        let opted_out = fs::read_to_string(dir.join(OPT_OUT_FILE))
+            .map(|s| s.lines().map(|l| l.trim().to_lowercase()).collect())
+            .unwrap_or_default();
+
+        Logger {
+            dir,
+            excluded: excluded.iter().map(|c| c.to_lowercase()).collect(),
+            opted_out: RwLock::new(opted_out),
+            members: Mutex::new(HashMap::new()),
+            phantom: PhantomData,
+        }
+    }
+
+    fn write(&self, network: &str, channel: &str, line: &str) -> Result<(), FrippyError> {
+        let channel = channel.to_lowercase();
+        if self.excluded.contains(&channel) {
+            return Ok(());
+        }
+
+        let now = Local::now();
+        let dir = self.dir.join(sanitize(network)).join(sanitize(&channel));
+        fs::create_dir_all(&dir).context(FrippyErrorKind::Logger)?;
+
+        let path = dir.join(format!("{}.log", now.format("%Y-%m-%d")));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(FrippyErrorKind::Logger)?;
+
+        writeln!(file, "{} {}", now.format("%H:%M"), line).context(FrippyErrorKind::Logger)?;
+
+        Ok(())
+    }
+
+    fn is_opted_out(&self, nick: &str) -> bool {
+        self.opted_out.read().contains(&nick.to_lowercase())
+    }
+
+    /// Returns `text` unless `nick` does not want to be logged.
+    fn redact<'a>(&self, nick: &str, text: &'a str) -> &'a str {
+        if self.is_opted_out(nick) {
+            REDACTED
+        } else {
+            text
+        }
+    }
+
+    fn set_opt_out(&self, nick: &str, opt_out: bool) -> Result<(), FrippyError> {
+        let mut opted_out = self.opted_out.write();
+        if opt_out {
+            opted_out.insert(nick.to_lowercase());
+        } else {
+            opted_out.remove(&nick.to_lowercase());
+        }
+
+        let mut nicks = opted_out.iter().cloned().collect::<Vec<_>>();
+        nicks.sort();
+
+        fs::create_dir_all(&self.dir).context(FrippyErrorKind::Logger)?;
+        fs::write(self.dir.join(OPT_OUT_FILE), nicks.join("\n"))
+            .context(FrippyErrorKind::Logger)?;
+
+        Ok(())
+    }
+
+    /// Returns the channels `nick` is known to be in.
+    fn channels_of(&self, nick: &str) -> Vec<String> {
+        let nick = nick.to_lowercase();
+        self.members
+            .lock()
+            .iter()
+            .filter(|(_, nicks)| nicks.contains(&nick))
+            .map(|(channel, _)| channel.clone())
+            .collect()
+    }
+
+    fn log(&self, client: &C, message: &Message) -> Result<(), FrippyError> {
+        let network = client
+            .config()
+            .server
+            .clone()
+            .unwrap_or_else(|| String::from("unknown"));
+        let nick = message.source_nickname().unwrap_or("");
+        let host = message
+            .prefix
+            .as_ref()
+            .and_then(|p| p.splitn(2, '!').nth(1))
+            .unwrap_or("");
+
+        match message.command {
+            Command::PRIVMSG(ref target, ref content) => {
+                // Private messages are never logged
+                if !target.starts_with('#') && !target.starts_with('&') {
+                    return Ok(());
+                }
+
+                let line = match action(content) {
+                    Some(action) => format!(" * {} {}", nick, self.redact(nick, action)),
+                    None => format!("<{}> {}", nick, self.redact(nick, content)),
+                };
+                self.write(&network, target, &line)
+            }
+            Command::JOIN(ref channel, _, _) => {
+                if nick == client.current_nickname() {
+                    self.members
+                        .lock()
+                        .insert(channel.to_lowercase(), HashSet::new());
+                }
+
+                self.members
+                    .lock()
+                    .entry(channel.to_lowercase())
+                    .or_default()
+                    .insert(nick.to_lowercase());
+
+                let line = format!("-!- {} [{}] has joined {}", nick, host, channel);
+                self.write(&network, channel, &line)
+            }
+            Command::PART(ref channel, ref reason) => {
+                if nick == client.current_nickname() {
+                    self.members.lock().remove(&channel.to_lowercase());
+                } else if let Some(nicks) = self.members.lock().get_mut(&channel.to_lowercase()) {
+                    nicks.remove(&nick.to_lowercase());
+                }
+
+                let reason = reason.as_ref().map_or("", |r| self.redact(nick, r));
+                let line = format!("-!- {} [{}] has left {} [{}]", nick, host, channel, reason);
+                self.write(&network, channel, &line)
+            }
+            Command::KICK(ref channel, ref victim, ref reason) => {
+                if victim == client.current_nickname() {
+                    self.members.lock().remove(&channel.to_lowercase());
+                } else if let Some(nicks) = self.members.lock().get_mut(&channel.to_lowercase()) {
+                    nicks.remove(&victim.to_lowercase());
+                }
+
+                let reason = reason.as_ref().map_or("", |r| self.redact(nick, r));
+                let line = format!(
+                    "-!- {} was kicked from {} by {} [{}]",
+                    victim, channel, nick, reason
+                );
+                self.write(&network, channel, &line)
+            }
+            Command::TOPIC(ref channel, Some(ref topic)) => {
+                let line = format!(
+                    "-!- {} changed the topic of {} to: {}",
+                    nick,
+                    channel,
+                    self.redact(nick, topic)
+                );
+                self.write(&network, channel, &line)
+            }
+            Command::QUIT(ref reason) => {
+                let reason = reason.as_ref().map_or("", |r| self.redact(nick, r));
+                let line = format!("-!- {} [{}] has quit [{}]", nick, host, reason);
+
+                for channel in self.channels_of(nick) {
+                    self.write(&network, &channel, &line)?;
+                }
+
+                for nicks in self.members.lock().values_mut() {
+                    nicks.remove(&nick.to_lowercase());
+                }
+
+                Ok(())
+            }
+            Command::NICK(ref new_nick) => {
+                let line = format!("-!- {} is now known as {}", nick, new_nick);
+
+                for channel in self.channels_of(nick) {
+                    self.write(&network, &channel, &line)?;
+                }
+
+                for nicks in self.members.lock().values_mut() {
+                    if nicks.remove(&nick.to_lowercase()) {
+                        nicks.insert(new_nick.to_lowercase());
+                    }
+                }
+
+                Ok(())
+            }
+            Command::Response(Response::RPL_NAMREPLY, ref args, Some(ref names)) => {
+                if let Some(channel) = args.last() {
+                    let mut members = self.members.lock();
+                    let nicks = members.entry(channel.to_lowercase()).or_default();
+
+                    for name in names.split_whitespace() {
+                        let name = name.trim_start_matches(|c| "~&@%+".contains(c));
+                        nicks.insert(name.to_lowercase());
+                    }
+                }
+
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn help(&self) -> &str {
+        "Logs channel events, send \"logger optout\" to have your messages redacted.\r\n\
+         usage: logger <optout|optin>"
+    }
+}
+
+/// Returns the text of a CTCP ACTION.
+fn action(content: &str) -> Option<&str> {
+    content
+        .strip_prefix("\u{1}ACTION ")
+        .map(|a| a.trim_end_matches('\u{1}'))
+}
+
+/// Makes a name safe to use as a directory.
+fn sanitize(name: &str) -> String {
+    name.replace(|c| c == '/' || c == '\\' || c == '\0', "_")
+        .trim_start_matches('.')
+        .to_owned()
+}
+
+impl<C: FrippyClient> Plugin for Logger<C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        match self.log(client, message) {
+            Ok(()) => ExecutionStatus::Done,
+            Err(e) => ExecutionStatus::Err(e),
+        }
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Logger should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        let response = match command.tokens.get(0).map(String::as_str) {
+            Some("optout") => self
+                .set_opt_out(&command.source, true)
+                .map(|_| "Your messages will be redacted from the logs"),
+            Some("optin") => self
+                .set_opt_out(&command.source, false)
+                .map(|_| "Your messages will be logged again"),
+            _ => Ok(self.help()),
+        };
+
+        let message = match response {
+            Ok(message) => message,
+            Err(ref e) => {
+                error!("Failed to save log opt-outs: {}", e);
+                "Failed to save your choice"
+            }
+        };
+
+        client
+            .send_notice(&command.source, message)
+            .context(FrippyErrorKind::Connection)?;
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from("This Plugin does not implement any commands."))
+    }
+}
@@ -1,11 +1,20 @@
 //! Collection of plugins included
 pub mod counter;
+pub mod external;
 pub mod factoid;
+pub mod feeds;
 pub mod help;
 pub mod keepnick;
+pub mod logger;
+pub mod native;
 pub mod quote;
 pub mod remind;
 pub mod sed;
+pub mod seen;
 pub mod tell;
 pub mod unicode;
 pub mod url;
+pub mod urls;
+pub mod webhook;
+#[cfg(feature = "wasm")]
+pub mod wasm;
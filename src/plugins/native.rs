@@ -0,0 +1,614 @@
+//! Loads plugins from shared libraries at runtime.
+//!
+//! Rust types are not guaranteed to have the same layout between compiler
+//! versions, so native plugins only talk to frippy through the `#[repr(C)]`
+//! structs in this module. Plugin crates implement [`NativePlugin`](trait.NativePlugin.html),
+//! are built as a `cdylib` and export themselves with
+//! [`export_native_plugin!`](../../macro.export_native_plugin.html).
+//!
+//! # Examples
+//! ```ignore
+//! use frippy::plugins::native::{Host, NativePlugin, NativeStatus};
+//! use frippy::export_native_plugin;
+//!
+//! struct Ping;
+//!
+//! impl NativePlugin for Ping {
+//!     fn name(&self) -> &str {
+//!         "Ping"
+//!     }
+//!
+//!     fn execute(&self, _: &Host, _: &Message) -> NativeStatus {
+//!         NativeStatus::Done
+//!     }
+//!
+//!     fn execute_threaded(&self, _: &Host, _: &Message) -> Result<(), String> {
+//!         Ok(())
+//!     }
+//!
+//!     fn command(&self, host: &Host, command: PluginCommand) -> Result<(), String> {
+//!         host.send_privmsg(&command.target, "pong")
+//!     }
+//!
+//!     fn evaluate(&self, _: &Host, _: PluginCommand) -> Result<String, String> {
+//!         Ok(String::from("pong"))
+//!     }
+//! }
+//!
+//! export_native_plugin!(Ping);
+//! ```
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::marker::PhantomData;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use irc::client::prelude::*;
+use libloading::Library;
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::{Fail, ResultExt};
+use log::{error, info};
+
+/// Increased whenever the layout of `PluginVTable` or `HostApi` changes.
+pub const ABI_VERSION: u32 = 1;
+
+/// The symbol every native plugin has to export.
+pub const CREATE_SYMBOL: &[u8] = b"frippy_plugin_create\0";
+
+pub const STATUS_DONE: c_int = 0;
+pub const STATUS_REQUIRES_THREAD: c_int = 1;
+pub const STATUS_ERROR: c_int = -1;
+
+/// Functions frippy provides to a plugin for the duration of a single call.
+/// Plugins must not keep a pointer to it after the call returned.
+#[repr(C)]
+pub struct HostApi {
+    pub context: *mut c_void,
+    pub send_raw: unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int,
+    pub log_error: unsafe extern "C" fn(*mut c_void, *const c_char),
+    pub reply: unsafe extern "C" fn(*mut c_void, *const c_char),
+}
+
+/// Functions a plugin provides to frippy.
+/// All strings are NUL terminated and UTF-8 encoded.
+#[repr(C)]
+pub struct PluginVTable {
+    pub abi_version: u32,
+    pub instance: *mut c_void,
+    pub name: unsafe extern "C" fn(*mut c_void) -> *const c_char,
+    pub execute: unsafe extern "C" fn(*mut c_void, *const HostApi, *const c_char) -> c_int,
+    pub execute_threaded: unsafe extern "C" fn(*mut c_void, *const HostApi, *const c_char) -> c_int,
+    pub command: unsafe extern "C" fn(
+        *mut c_void,
+        *const HostApi,
+        *const c_char,
+        *const c_char,
+        *const *const c_char,
+        usize,
+    ) -> c_int,
+    pub evaluate: unsafe extern "C" fn(
+        *mut c_void,
+        *const HostApi,
+        *const c_char,
+        *const c_char,
+        *const *const c_char,
+        usize,
+    ) -> c_int,
+    pub destroy: unsafe extern "C" fn(*mut c_void),
+}
+
+/// The plugin side equivalent of [`ExecutionStatus`](../../plugin/enum.ExecutionStatus.html).
+#[derive(Debug)]
+pub enum NativeStatus {
+    Done,
+    Err(String),
+    RequiresThread,
+}
+
+/// The plugin side equivalent of [`Plugin`](../../plugin/trait.Plugin.html).
+pub trait NativePlugin: Send + Sync {
+    fn name(&self) -> &str;
+    fn execute(&self, host: &Host, message: &Message) -> NativeStatus;
+    fn execute_threaded(&self, host: &Host, message: &Message) -> Result<(), String>;
+    fn command(&self, host: &Host, command: PluginCommand) -> Result<(), String>;
+    fn evaluate(&self, host: &Host, command: PluginCommand) -> Result<String, String>;
+}
+
+/// Gives a native plugin access to the IRC connection it was called from.
+pub struct Host<'a> {
+    api: &'a HostApi,
+}
+
+impl<'a> Host<'a> {
+    pub fn send_raw(&self, line: &str) -> Result<(), String> {
+        let line = CString::new(line).map_err(|e| e.to_string())?;
+
+        match unsafe { (self.api.send_raw)(self.api.context, line.as_ptr()) } {
+            STATUS_DONE => Ok(()),
+            _ => Err(String::from("Failed to send message")),
+        }
+    }
+
+    pub fn send_privmsg(&self, target: &str, message: &str) -> Result<(), String> {
+        for line in message.split("\r\n") {
+            self.send_raw(&format!("PRIVMSG {} :{}", target, line))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn send_notice(&self, target: &str, message: &str) -> Result<(), String> {
+        for line in message.split("\r\n") {
+            self.send_raw(&format!("NOTICE {} :{}", target, line))?;
+        }
+
+        Ok(())
+    }
+
+    fn log_error(&self, message: &str) {
+        let message = CString::new(message.replace('\0', "")).expect("NUL characters were removed");
+        unsafe { (self.api.log_error)(self.api.context, message.as_ptr()) }
+    }
+
+    fn reply(&self, message: &str) {
+        let message = CString::new(message.replace('\0', "")).expect("NUL characters were removed");
+        unsafe { (self.api.reply)(self.api.context, message.as_ptr()) }
+    }
+}
+
+/// Exports a [`NativePlugin`](plugins/native/trait.NativePlugin.html)
+/// so frippy can load it from a `cdylib`.
+#[macro_export]
+macro_rules! export_native_plugin {
+    ($plugin:expr) => {
+        #[no_mangle]
+        pub extern "C" fn frippy_plugin_create() -> $crate::plugins::native::PluginVTable {
+            $crate::plugins::native::vtable_for($plugin)
+        }
+    };
+}
+
+struct Shim<P> {
+    plugin: P,
+    name: CString,
+}
+
+/// Wraps `plugin` in a `PluginVTable`, this is used by `export_native_plugin!`.
+pub fn vtable_for<P: NativePlugin + 'static>(plugin: P) -> PluginVTable {
+    let name = CString::new(plugin.name()).expect("Plugin names can not contain NUL");
+    let shim = Box::new(Shim { plugin, name });
+
+    PluginVTable {
+        abi_version: ABI_VERSION,
+        instance: Box::into_raw(shim) as *mut c_void,
+        name: shim_name::<P>,
+        execute: shim_execute::<P>,
+        execute_threaded: shim_execute_threaded::<P>,
+        command: shim_command::<P>,
+        evaluate: shim_evaluate::<P>,
+        destroy: shim_destroy::<P>,
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str, String> {
+    CStr::from_ptr(s).to_str().map_err(|e| e.to_string())
+}
+
+unsafe fn to_message(line: *const c_char) -> Result<Message, String> {
+    to_str(line)?.parse::<Message>().map_err(|e| e.to_string())
+}
+
+unsafe fn to_command(
+    source: *const c_char,
+    target: *const c_char,
+    tokens: *const *const c_char,
+    len: usize,
+) -> Result<PluginCommand, String> {
+    let tokens = (0..len)
+        .map(|i| to_str(*tokens.add(i)).map(ToOwned::to_owned))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(PluginCommand {
+        source: to_str(source)?.to_owned(),
+        hostmask: None,
+        target: to_str(target)?.to_owned(),
+        tokens,
+    })
+}
+
+/// Runs `f` and makes sure neither errors nor panics cross the ABI boundary.
+fn guard<F>(host: &Host, f: F) -> c_int
+where
+    F: FnOnce() -> Result<c_int, String>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(e)) => {
+            host.log_error(&e);
+            STATUS_ERROR
+        }
+        Err(_) => {
+            host.log_error("The plugin panicked");
+            STATUS_ERROR
+        }
+    }
+}
+
+unsafe extern "C" fn shim_name<P: NativePlugin>(instance: *mut c_void) -> *const c_char {
+    (*(instance as *const Shim<P>)).name.as_ptr()
+}
+
+unsafe extern "C" fn shim_execute<P: NativePlugin>(
+    instance: *mut c_void,
+    api: *const HostApi,
+    line: *const c_char,
+) -> c_int {
+    let shim = &*(instance as *const Shim<P>);
+    let host = Host { api: &*api };
+
+    guard(&host, || {
+        match shim.plugin.execute(&host, &to_message(line)?) {
+            NativeStatus::Done => Ok(STATUS_DONE),
+            NativeStatus::RequiresThread => Ok(STATUS_REQUIRES_THREAD),
+            NativeStatus::Err(e) => Err(e),
+        }
+    })
+}
+
+unsafe extern "C" fn shim_execute_threaded<P: NativePlugin>(
+    instance: *mut c_void,
+    api: *const HostApi,
+    line: *const c_char,
+) -> c_int {
+    let shim = &*(instance as *const Shim<P>);
+    let host = Host { api: &*api };
+
+    guard(&host, || {
+        shim.plugin
+            .execute_threaded(&host, &to_message(line)?)
+            .map(|()| STATUS_DONE)
+    })
+}
+
+unsafe extern "C" fn shim_command<P: NativePlugin>(
+    instance: *mut c_void,
+    api: *const HostApi,
+    source: *const c_char,
+    target: *const c_char,
+    tokens: *const *const c_char,
+    len: usize,
+) -> c_int {
+    let shim = &*(instance as *const Shim<P>);
+    let host = Host { api: &*api };
+
+    guard(&host, || {
+        let command = to_command(source, target, tokens, len)?;
+        shim.plugin.command(&host, command).map(|()| STATUS_DONE)
+    })
+}
+
+unsafe extern "C" fn shim_evaluate<P: NativePlugin>(
+    instance: *mut c_void,
+    api: *const HostApi,
+    source: *const c_char,
+    target: *const c_char,
+    tokens: *const *const c_char,
+    len: usize,
+) -> c_int {
+    let shim = &*(instance as *const Shim<P>);
+    let host = Host { api: &*api };
+
+    // Evaluation errors are meant for the user so they are not logged
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        shim.plugin
+            .evaluate(&host, to_command(source, target, tokens, len)?)
+    }));
+
+    match result {
+        Ok(Ok(response)) => {
+            host.reply(&response);
+            STATUS_DONE
+        }
+        Ok(Err(e)) => {
+            host.reply(&e);
+            STATUS_ERROR
+        }
+        Err(_) => {
+            host.log_error("The plugin panicked");
+            STATUS_ERROR
+        }
+    }
+}
+
+unsafe extern "C" fn shim_destroy<P: NativePlugin>(instance: *mut c_void) {
+    drop(Box::from_raw(instance as *mut Shim<P>));
+}
+
+/// State frippy passes to the `HostApi` functions.
+struct HostContext<'a, C> {
+    client: &'a C,
+    name: &'a str,
+    reply: RefCell<Option<String>>,
+}
+
+unsafe extern "C" fn host_send_raw<C: FrippyClient>(
+    context: *mut c_void,
+    line: *const c_char,
+) -> c_int {
+    let context = &*(context as *const HostContext<C>);
+
+    let message = match to_message(line) {
+        Ok(message) => message,
+        Err(e) => {
+            error!("{} tried to send an invalid message: {}", context.name, e);
+            return STATUS_ERROR;
+        }
+    };
+
+    match context.client.send(message) {
+        Ok(()) => STATUS_DONE,
+        Err(e) => {
+            error!("{} failed to send a message: {}", context.name, e);
+            STATUS_ERROR
+        }
+    }
+}
+
+unsafe extern "C" fn host_log_error<C>(context: *mut c_void, message: *const c_char) {
+    let context = &*(context as *const HostContext<C>);
+    error!(
+        "{}: {}",
+        context.name,
+        CStr::from_ptr(message).to_string_lossy()
+    );
+}
+
+unsafe extern "C" fn host_reply<C>(context: *mut c_void, message: *const c_char) {
+    let context = &*(context as *const HostContext<C>);
+    let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+    *context.reply.borrow_mut() = Some(message);
+}
+
+/// A plugin loaded from a shared library.
+pub struct Native<C> {
+    name: String,
+    vtable: PluginVTable,
+    // The library has to outlive the vtable so it is dropped last
+    _library: Library,
+    phantom: PhantomData<C>,
+}
+
+// The vtable only contains raw pointers to an instance of a
+// `NativePlugin` which itself has to be `Send` and `Sync`.
+unsafe impl<C: Send> Send for Native<C> {}
+unsafe impl<C: Sync> Sync for Native<C> {}
+
+impl<C: FrippyClient> Native<C> {
+    /// Loads the plugin exported by the shared library at `path`.
+    pub fn load(path: &Path) -> Result<Self, NativeError> {
+        let library = unsafe { Library::new(path) }.context(ErrorKind::Load)?;
+
+        let vtable = unsafe {
+            let create = library
+                .get::<unsafe extern "C" fn() -> PluginVTable>(CREATE_SYMBOL)
+                .context(ErrorKind::MissingSymbol)?;
+            create()
+        };
+
+        if vtable.abi_version != ABI_VERSION {
+            // The layout is unknown so destroy can not be called safely
+            Err(ErrorKind::AbiMismatch)?;
+        }
+
+        let name = unsafe { CStr::from_ptr((vtable.name)(vtable.instance)) }
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(Native {
+            name,
+            vtable,
+            _library: library,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Loads all shared libraries in `dir`.
+    /// Libraries which fail to load are logged and skipped.
+    pub fn load_dir(dir: &Path) -> Result<Vec<Self>, NativeError> {
+        let mut plugins = Vec::new();
+
+        for entry in fs::read_dir(dir).context(ErrorKind::Load)? {
+            let path = entry.context(ErrorKind::Load)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(std::env::consts::DLL_EXTENSION) {
+                continue;
+            }
+
+            match Self::load(&path) {
+                Ok(plugin) => {
+                    info!("Loaded {} from {}", plugin.name, path.display());
+                    plugins.push(plugin);
+                }
+                Err(e) => error!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(plugins)
+    }
+
+    /// Calls `f` with a `HostApi` which is valid for the duration of the call
+    /// and returns the status together with the reply set by the plugin.
+    fn call<F>(&self, client: &C, f: F) -> (c_int, Option<String>)
+    where
+        F: FnOnce(&HostApi) -> c_int,
+    {
+        let context = HostContext {
+            client,
+            name: &self.name,
+            reply: RefCell::new(None),
+        };
+
+        let api = HostApi {
+            context: &context as *const HostContext<C> as *mut c_void,
+            send_raw: host_send_raw::<C>,
+            log_error: host_log_error::<C>,
+            reply: host_reply::<C>,
+        };
+
+        let status = f(&api);
+
+        (status, context.reply.into_inner())
+    }
+
+    fn call_message(&self, client: &C, message: &Message, threaded: bool) -> c_int {
+        let line = match CString::new(message.to_string().trim_end()) {
+            Ok(line) => line,
+            Err(_) => return STATUS_ERROR,
+        };
+
+        let function = if threaded {
+            self.vtable.execute_threaded
+        } else {
+            self.vtable.execute
+        };
+
+        self.call(client, |api| unsafe {
+            function(self.vtable.instance, api, line.as_ptr())
+        })
+        .0
+    }
+
+    fn call_command(
+        &self,
+        client: &C,
+        command: &PluginCommand,
+        evaluate: bool,
+    ) -> Result<(c_int, Option<String>), NativeError> {
+        let source = CString::new(command.source.as_str()).context(ErrorKind::InvalidString)?;
+        let target = CString::new(command.target.as_str()).context(ErrorKind::InvalidString)?;
+        let tokens = command
+            .tokens
+            .iter()
+            .map(|t| CString::new(t.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .context(ErrorKind::InvalidString)?;
+        let pointers = tokens.iter().map(|t| t.as_ptr()).collect::<Vec<_>>();
+
+        let function = if evaluate {
+            self.vtable.evaluate
+        } else {
+            self.vtable.command
+        };
+
+        Ok(self.call(client, |api| unsafe {
+            function(
+                self.vtable.instance,
+                api,
+                source.as_ptr(),
+                target.as_ptr(),
+                pointers.as_ptr(),
+                pointers.len(),
+            )
+        }))
+    }
+}
+
+impl<C> Drop for Native<C> {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.destroy)(self.vtable.instance) }
+    }
+}
+
+impl<C> PluginName for Native<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<C: FrippyClient> Plugin for Native<C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        match self.call_message(client, message, false) {
+            STATUS_DONE => ExecutionStatus::Done,
+            STATUS_REQUIRES_THREAD => ExecutionStatus::RequiresThread,
+            _ => ExecutionStatus::Err(
+                NativeError::from(ErrorKind::Failed)
+                    .context(FrippyErrorKind::Native)
+                    .into(),
+            ),
+        }
+    }
+
+    fn execute_threaded(
+        &self,
+        client: &Self::Client,
+        message: &Message,
+    ) -> Result<(), FrippyError> {
+        match self.call_message(client, message, true) {
+            STATUS_DONE => Ok(()),
+            _ => Err(NativeError::from(ErrorKind::Failed)).context(FrippyErrorKind::Native)?,
+        }
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        match self
+            .call_command(client, &command, false)
+            .context(FrippyErrorKind::Native)?
+        {
+            (STATUS_DONE, _) => Ok(()),
+            _ => Err(NativeError::from(ErrorKind::Failed)).context(FrippyErrorKind::Native)?,
+        }
+    }
+
+    fn evaluate(&self, client: &Self::Client, command: PluginCommand) -> Result<String, String> {
+        match self.call_command(client, &command, true) {
+            Ok((STATUS_DONE, reply)) => Ok(reply.unwrap_or_default()),
+            Ok((_, Some(reply))) => Err(reply),
+            Ok((_, None)) => Err(ErrorKind::Failed.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Native<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native {{ name: {:?} }}", self.name)
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "NativeError"]
+    pub enum ErrorKind {
+        /// Load error
+        #[fail(display = "Failed to load the library")]
+        Load,
+
+        /// Missing symbol error
+        #[fail(display = "The library does not export frippy_plugin_create")]
+        MissingSymbol,
+
+        /// ABI mismatch error
+        #[fail(display = "The library was built for a different plugin ABI version")]
+        AbiMismatch,
+
+        /// Invalid string error
+        #[fail(display = "Strings passed to native plugins can not contain NUL")]
+        InvalidString,
+
+        /// Failed error
+        #[fail(display = "The native plugin failed")]
+        Failed,
+    }
+}
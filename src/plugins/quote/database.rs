@@ -13,6 +13,9 @@ use r2d2::Pool;
 #[cfg(feature = "mysql")]
 use r2d2_diesel::ConnectionManager;
 
+#[cfg(feature = "mysql")]
+use crate::utils::escape_like;
+
 use chrono::NaiveDateTime;
 
 use super::error::*;
@@ -59,6 +62,16 @@ pub trait Database: Send + Sync {
         channel: &str,
         offset: i32,
     ) -> Result<Quote, QuoteError>;
+
+    fn search_quotes(&self, query: &str) -> Result<Vec<Quote>, QuoteError>;
+    fn update_quote(
+        &mut self,
+        quotee: &str,
+        channel: &str,
+        idx: i32,
+        content: &str,
+    ) -> Result<(), QuoteError>;
+    fn delete_quote(&mut self, quotee: &str, channel: &str, idx: i32) -> Result<(), QuoteError>;
 }
 
 // HashMap
@@ -155,6 +168,48 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database
 
         Ok(quote)
     }
+
+    fn search_quotes(&self, query: &str) -> Result<Vec<Quote>, QuoteError> {
+        let query = query.to_lowercase();
+        let mut quotes = self
+            .values()
+            .filter(|q| {
+                q.quotee.to_lowercase().contains(&query)
+                    || q.content.to_lowercase().contains(&query)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        quotes.sort_by(|a, b| {
+            a.channel
+                .cmp(&b.channel)
+                .then(a.quotee.cmp(&b.quotee))
+                .then(a.idx.cmp(&b.idx))
+        });
+        Ok(quotes)
+    }
+
+    fn update_quote(
+        &mut self,
+        quotee: &str,
+        channel: &str,
+        idx: i32,
+        content: &str,
+    ) -> Result<(), QuoteError> {
+        let quote = self
+            .get_mut(&(quotee.to_owned(), channel.to_owned(), idx))
+            .ok_or(ErrorKind::NotFound)?;
+
+        quote.content = content.to_owned();
+        Ok(())
+    }
+
+    fn delete_quote(&mut self, quotee: &str, channel: &str, idx: i32) -> Result<(), QuoteError> {
+        match self.remove(&(quotee.to_owned(), channel.to_owned(), idx)) {
+            Some(_) => Ok(()),
+            None => Err(ErrorKind::NotFound)?,
+        }
+    }
 }
 
 // Diesel automatically defines the quotes module as public.
@@ -273,4 +328,54 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
 
         Ok(quote)
     }
+
+    fn search_quotes(&self, query: &str) -> Result<Vec<Quote>, QuoteError> {
+        use self::quotes::columns;
+
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        let pattern = format!("%{}%", escape_like(query));
+
+        Ok(quotes::table
+            .filter(
+                columns::quotee
+                    .like(&pattern)
+                    .or(columns::content.like(&pattern)),
+            )
+            .order((
+                columns::channel.asc(),
+                columns::quotee.asc(),
+                columns::idx.asc(),
+            ))
+            .load::<Quote>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn update_quote(
+        &mut self,
+        quotee: &str,
+        channel: &str,
+        idx: i32,
+        content: &str,
+    ) -> Result<(), QuoteError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        match diesel::update(quotes::table.find((quotee, channel, idx)))
+            .set(quotes::columns::content.eq(content))
+            .execute(conn)
+        {
+            Ok(0) => Err(ErrorKind::NotFound)?,
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+    }
+
+    fn delete_quote(&mut self, quotee: &str, channel: &str, idx: i32) -> Result<(), QuoteError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        match diesel::delete(quotes::table.find((quotee, channel, idx))).execute(conn) {
+            Ok(0) => Err(ErrorKind::NotFound)?,
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+    }
 }
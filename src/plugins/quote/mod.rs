@@ -3,6 +3,7 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use antidote::{Mutex, RwLock};
 use chrono::NaiveDateTime;
@@ -35,7 +36,7 @@ enum PreviousCommand {
 
 #[derive(PluginName)]
 pub struct Quote<T: Database, C: Client> {
-    quotes: RwLock<T>,
+    quotes: Arc<RwLock<T>>,
     previous_map: Mutex<HashMap<String, PreviousCommand>>,
     phantom: PhantomData<C>,
     random_index: Mutex<RandomIndex>,
@@ -46,13 +47,18 @@ impl<T: Database, C: Client> Quote<T, C> {
         let random_index = RandomIndex::new();
 
         Quote {
-            quotes: RwLock::new(db),
+            quotes: Arc::new(RwLock::new(db)),
             previous_map: Mutex::new(HashMap::new()),
             phantom: PhantomData,
             random_index: Mutex::new(random_index),
         }
     }
 
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.quotes)
+    }
+
     fn create_quote(
         &self,
         quotee: &str,
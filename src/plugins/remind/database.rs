@@ -61,6 +61,7 @@ pub trait Database: Send + Sync {
     fn update_event_time(&mut self, id: i64, time: &NaiveDateTime) -> Result<(), RemindError>;
     fn get_events_before(&self, time: &NaiveDateTime) -> Result<Vec<Event>, RemindError>;
     fn get_user_events(&self, user: &str) -> Result<Vec<Event>, RemindError>;
+    fn get_all_events(&self) -> Result<Vec<Event>, RemindError>;
     fn get_event(&self, id: i64) -> Result<Event, RemindError>;
     fn delete_event(&mut self, id: i64) -> Result<(), RemindError>;
 }
@@ -131,6 +132,13 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<i64, Event,
         }
     }
 
+    fn get_all_events(&self) -> Result<Vec<Event>, RemindError> {
+        let mut events = self.values().cloned().collect::<Vec<_>>();
+        events.sort_by_key(|e| e.id);
+
+        Ok(events)
+    }
+
     fn get_event(&self, id: i64) -> Result<Event, RemindError> {
         Ok(self.get(&id).cloned().ok_or(ErrorKind::NotFound)?)
     }
@@ -212,6 +220,16 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
             .context(ErrorKind::MysqlError)?)
     }
 
+    fn get_all_events(&self) -> Result<Vec<Event>, RemindError> {
+        use self::events::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(events::table
+            .order(columns::id.asc())
+            .load::<Event>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
     fn get_event(&self, id: i64) -> Result<Event, RemindError> {
         let conn = &*self.get().context(ErrorKind::NoConnection)?;
 
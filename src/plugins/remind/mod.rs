@@ -112,6 +112,11 @@ impl<T: Database + 'static, C: FrippyClient> Remind<T, C> {
         }
     }
 
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.events)
+    }
+
     fn user_cmd(&self, command: PluginCommand) -> Result<String, RemindError> {
         let parser = CommandParser::parse_target(command.tokens)?;
 
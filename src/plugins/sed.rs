@@ -4,11 +4,12 @@ use std::marker::PhantomData;
 use antidote::RwLock;
 use circular_queue::CircularQueue;
 use lazy_static::lazy_static;
-use regex::{Captures, Regex, RegexBuilder};
+use regex::{Regex, RegexBuilder};
 
 use irc::client::prelude::*;
 
 use crate::plugin::*;
+use crate::utils::irc_lowercase;
 use crate::FrippyClient;
 
 use self::error::*;
@@ -21,95 +22,284 @@ use log::debug;
 use frippy_derive::PluginName;
 
 lazy_static! {
-    static ref RE: Regex =
-        Regex::new(r"^s/((?:\\/|[^/])+)/((?:\\/|[^/])*)/(?:(\w+))?\s*$").unwrap();
+    /// Matches the nick of the user to correct in "nick: s/a/b/"
+    static ref TARGET_RE: Regex = Regex::new(r"^([^\s:,/;]+)[:,]\s*(.*)$").unwrap();
 }
 
-#[derive(PluginName, Debug)]
-pub struct Sed<C> {
-    per_channel: usize,
-    channel_messages: RwLock<HashMap<String, CircularQueue<String>>>,
-    phantom: PhantomData<C>,
+/// A command as it was written, before its regex was compiled.
+#[derive(Clone, PartialEq, Debug)]
+struct RawCommand {
+    kind: char,
+    first: String,
+    second: String,
+    flags: String,
 }
 
-impl<C: FrippyClient> Sed<C> {
-    pub fn new(per_channel: usize) -> Self {
-        Sed {
-            per_channel,
-            channel_messages: RwLock::new(HashMap::new()),
-            phantom: PhantomData,
+/// A compiled command which can be applied to a message.
+#[derive(Debug)]
+enum Edit {
+    Substitute {
+        regex: Regex,
+        replacement: String,
+        global: bool,
+        /// The first occurrence to replace, starting at 1
+        nth: usize,
+    },
+    Transliterate {
+        from: Vec<char>,
+        to: Vec<char>,
+    },
+}
+
+impl Edit {
+    fn from_raw(raw: &RawCommand) -> Result<Self, SedError> {
+        if raw.kind == 'y' {
+            let from = raw.first.chars().collect::<Vec<_>>();
+            let to = raw.second.chars().collect::<Vec<_>>();
+            if from.len() != to.len() {
+                Err(ErrorKind::InvalidTransliteration)?;
+            }
+
+            return Ok(Edit::Transliterate { from, to });
+        }
+
+        let mut global = false;
+        let mut case_insens = false;
+        let mut ign_whitespace = false;
+        let mut swap_greed = false;
+        let mut enable_unicode = true;
+        let mut nth = String::new();
+
+        for flag in raw.flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => case_insens = true,
+                'x' => ign_whitespace = true,
+                'U' => swap_greed = true,
+                'u' => enable_unicode = false,
+                '0'..='9' => nth.push(flag),
+                _ => Err(ErrorKind::InvalidFlags)?,
+            }
         }
+
+        let nth = if nth.is_empty() {
+            1
+        } else {
+            match nth.parse() {
+                Ok(0) | Err(_) => Err(ErrorKind::InvalidFlags)?,
+                Ok(nth) => nth,
+            }
+        };
+
+        let regex = RegexBuilder::new(&raw.first)
+            .case_insensitive(case_insens)
+            .ignore_whitespace(ign_whitespace)
+            .unicode(enable_unicode)
+            .swap_greed(swap_greed)
+            .build()
+            .context(ErrorKind::InvalidRegex)?;
+
+        Ok(Edit::Substitute {
+            regex,
+            replacement: raw.second.clone(),
+            global,
+            nth,
+        })
     }
 
-    fn add_message(&self, channel: String, message: String) {
-        let mut channel_messages = self.channel_messages.write();
-        let messages = channel_messages
-            .entry(channel)
-            .or_insert_with(|| CircularQueue::with_capacity(self.per_channel));
-        messages.push(message);
+    /// Returns the edited text or `None` if the command did not apply.
+    fn apply(&self, text: &str) -> Option<String> {
+        match *self {
+            Edit::Substitute {
+                ref regex,
+                ref replacement,
+                global,
+                nth,
+            } => {
+                let mut output = String::with_capacity(text.len());
+                let mut last = 0;
+                let mut replaced = false;
+
+                for (i, captures) in regex.captures_iter(text).enumerate() {
+                    if i + 1 < nth {
+                        continue;
+                    } else if i + 1 > nth && !global {
+                        break;
+                    }
+
+                    let found = captures.get(0).unwrap();
+                    output.push_str(&text[last..found.start()]);
+                    captures.expand(replacement, &mut output);
+                    last = found.end();
+                    replaced = true;
+                }
+
+                if !replaced {
+                    return None;
+                }
+
+                output.push_str(&text[last..]);
+                Some(output)
+            }
+            Edit::Transliterate { ref from, ref to } => {
+                if !text.contains(|c| from.contains(&c)) {
+                    return None;
+                }
+
+                Some(
+                    text.chars()
+                        .map(|c| from.iter().position(|&f| f == c).map_or(c, |i| to[i]))
+                        .collect(),
+                )
+            }
+        }
     }
+}
 
-    fn format_escaped(&self, input: &str) -> String {
-        let mut output = String::with_capacity(input.len());
-        let mut escape = false;
+/// Reads a part of a command up to the next unescaped delimiter.
+///
+/// An escaped delimiter stands for itself. In patterns all other escapes
+/// are kept for the regex, elsewhere only `\\` needs to be unescaped.
+fn parse_field(input: &str, delimiter: char, pattern: bool) -> Option<(String, &str)> {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return Some((output, &input[i + c.len_utf8()..]));
+        } else if c != '\\' {
+            output.push(c);
+            continue;
+        }
 
-        for c in input.chars() {
-            if escape && !r"/\".contains(c) {
+        match chars.next() {
+            Some((_, c)) if c == delimiter && pattern => {
+                output.push_str(&regex::escape(&c.to_string()))
+            }
+            Some((_, c)) if c == delimiter || (c == '\\' && !pattern) => output.push(c),
+            Some((_, c)) => {
                 output.push('\\');
-            } else if !escape && c == '\\' {
-                escape = true;
-                continue;
+                output.push(c);
             }
-            escape = false;
+            None => return None,
+        }
+    }
 
-            output.push(c);
+    None
+}
+
+/// Splits a line like "s|a|b|g; y/abc/xyz/" into its commands.
+/// Returns `None` if the line is not meant for sed.
+fn parse_commands(input: &str) -> Option<Vec<RawCommand>> {
+    let mut commands = Vec::new();
+    let mut rest = input.trim();
+
+    loop {
+        let mut chars = rest.chars();
+        let kind = chars.next()?;
+        let delimiter = chars.next()?;
+        if (kind != 's' && kind != 'y') || !delimiter.is_ascii_punctuation() || delimiter == '\\' {
+            return None;
         }
 
-        output
+        let (first, after_first) = parse_field(&rest[2..], delimiter, kind == 's')?;
+        let (second, after_second) = parse_field(after_first, delimiter, false)?;
+        let flags_end = after_second
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or_else(|| after_second.len());
+        let (flags, after_flags) = after_second.split_at(flags_end);
+
+        if first.is_empty() || (kind == 'y' && !flags.is_empty()) {
+            return None;
+        }
+
+        commands.push(RawCommand {
+            kind,
+            first,
+            second,
+            flags: flags.to_owned(),
+        });
+
+        rest = after_flags.trim_start();
+        if rest.is_empty() {
+            return Some(commands);
+        }
+
+        rest = rest.strip_prefix(';')?.trim_start();
     }
+}
 
-    fn run_regex(&self, channel: &str, captures: &Captures) -> Result<String, SedError> {
-        let mut global_match = false;
-        let mut case_insens = false;
-        let mut ign_whitespace = false;
-        let mut swap_greed = false;
-        let mut enable_unicode = true;
+/// Splits a line into the user it addresses and the commands.
+fn parse(input: &str) -> Option<(Option<&str>, Vec<RawCommand>)> {
+    if let Some(commands) = parse_commands(input) {
+        return Some((None, commands));
+    }
 
-        debug!("{:?}", captures);
+    let captures = TARGET_RE.captures(input)?;
+    let commands = parse_commands(captures.get(2).unwrap().as_str())?;
 
-        let first = self.format_escaped(captures.get(1).unwrap().as_str());
-        let second = self.format_escaped(captures.get(2).unwrap().as_str());
+    Some((Some(captures.get(1).unwrap().as_str()), commands))
+}
 
-        if let Some(flags) = captures.get(3) {
-            let flags = flags.as_str();
+#[derive(PluginName, Debug)]
+pub struct Sed<C> {
+    per_channel: usize,
+    /// The recent messages of each channel and their authors
+    channel_messages: RwLock<HashMap<String, CircularQueue<(String, String)>>>,
+    phantom: PhantomData<C>,
+}
 
-            global_match = flags.contains('g');
-            case_insens = flags.contains('i');
-            ign_whitespace = flags.contains('x');
-            swap_greed = flags.contains('U');
-            enable_unicode = !flags.contains('u');
+impl<C: FrippyClient> Sed<C> {
+    pub fn new(per_channel: usize) -> Self {
+        Sed {
+            per_channel,
+            channel_messages: RwLock::new(HashMap::new()),
+            phantom: PhantomData,
         }
+    }
 
-        let user_re = RegexBuilder::new(&first)
-            .case_insensitive(case_insens)
-            .ignore_whitespace(ign_whitespace)
-            .unicode(enable_unicode)
-            .swap_greed(swap_greed)
-            .build()
-            .context(ErrorKind::InvalidRegex)?;
+    fn add_message(&self, channel: String, author: String, message: String) {
+        let mut channel_messages = self.channel_messages.write();
+        let messages = channel_messages
+            .entry(channel)
+            .or_insert_with(|| CircularQueue::with_capacity(self.per_channel));
+        messages.push((author, message));
+    }
+
+    /// Applies the commands to the newest message of `target` which the
+    /// first command applies to and returns its author and the new text.
+    fn run_commands(
+        &self,
+        channel: &str,
+        target: &str,
+        commands: &[RawCommand],
+    ) -> Result<(String, String), SedError> {
+        debug!("{:?}", commands);
+
+        let edits = commands
+            .iter()
+            .map(Edit::from_raw)
+            .collect::<Result<Vec<_>, _>>()?;
+        let (first, rest) = edits.split_first().ok_or(ErrorKind::NoMatch)?;
 
         let channel_messages = self.channel_messages.read();
         let messages = channel_messages.get(channel).ok_or(ErrorKind::NoMessages)?;
 
-        for message in messages.iter() {
-            if user_re.is_match(message) {
-                let response = if global_match {
-                    user_re.replace_all(message, &second[..])
-                } else {
-                    user_re.replace(message, &second[..])
-                };
+        let target = irc_lowercase(target);
+        for (author, message) in messages.iter() {
+            if irc_lowercase(author) != target {
+                continue;
+            }
+
+            if let Some(mut text) = first.apply(message) {
+                // Like sed, later commands which do not match change nothing
+                for edit in rest {
+                    if let Some(edited) = edit.apply(&text) {
+                        text = edited;
+                    }
+                }
 
-                return Ok(response.to_string());
+                return Ok((author.clone(), text));
             }
         }
 
@@ -128,9 +318,16 @@ impl<C: FrippyClient> Plugin for Sed<C> {
                     return ExecutionStatus::Done;
                 }
 
-                if let Some(captures) = RE.captures(content) {
-                    let result = match self.run_regex(channel, &captures) {
-                        Ok(msg) => client.send_privmsg(channel, msg),
+                if let Some((target, commands)) = parse(content) {
+                    let target = target.unwrap_or(user);
+                    let result = match self.run_commands(channel, target, &commands) {
+                        Ok((author, text)) => {
+                            let response = format!("<{}> meant: {}", author, text);
+
+                            // The correction can be corrected again
+                            self.add_message(channel.to_string(), author, text);
+                            client.send_privmsg(channel, response)
+                        }
                         Err(e) => match e.kind() {
                             ErrorKind::InvalidRegex => {
                                 let err = e.cause().unwrap().to_string();
@@ -147,7 +344,7 @@ impl<C: FrippyClient> Plugin for Sed<C> {
                         Ok(_) => ExecutionStatus::Done,
                     }
                 } else {
-                    self.add_message(channel.to_string(), content.to_string());
+                    self.add_message(channel.to_string(), user.to_string(), content.to_string());
 
                     ExecutionStatus::Done
                 }
@@ -176,6 +373,22 @@ impl<C: FrippyClient> Plugin for Sed<C> {
             "Evaluation of commands is not implemented for sed at this time",
         ))
     }
+
+    fn inspect(&self) -> String {
+        self.channel_messages
+            .read()
+            .iter()
+            .map(|(channel, messages)| {
+                format!(
+                    "{}: {} of {} messages buffered",
+                    channel,
+                    messages.len(),
+                    self.per_channel
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 pub mod error {
@@ -189,12 +402,123 @@ pub mod error {
         #[fail(display = "Invalid regex")]
         InvalidRegex,
 
+        /// Invalid flags error
+        #[fail(display = "Invalid flags")]
+        InvalidFlags,
+
+        /// Invalid transliteration error
+        #[fail(display = "Both parts of y/// need the same number of characters")]
+        InvalidTransliteration,
+
         /// No messages found error
         #[fail(display = "No messages were found for this channel")]
         NoMessages,
 
         /// No match found error
-        #[fail(display = "No recent messages match this regex")]
+        #[fail(display = "No recent messages of that user match this regex")]
         NoMatch,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `line` on a channel where bob said `text`.
+    fn sed(line: &str, text: &str) -> Result<String, ErrorKind> {
+        let sed = Sed::<IrcClient>::new(10);
+        sed.add_message(String::from("#test"), String::from("bob"), text.to_owned());
+
+        let commands = parse_commands(line).expect("line should be a command");
+        sed.run_commands("#test", "bob", &commands)
+            .map(|(_, text)| text)
+            .map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(sed("s/a/b/", "banana"), Ok(String::from("bbnana")));
+        assert_eq!(sed("s|a|b|", "banana"), Ok(String::from("bbnana")));
+        assert_eq!(sed("s#a#b#g", "banana"), Ok(String::from("bbnbnb")));
+        assert_eq!(sed("s,a,b,", "banana"), Ok(String::from("bbnana")));
+    }
+
+    #[test]
+    fn escaped_delimiters() {
+        assert_eq!(sed(r"s/a\/b/c/", "x a/b y"), Ok(String::from("x c y")));
+        assert_eq!(sed(r"s/x/1\/2/", "x"), Ok(String::from("1/2")));
+        // The escaped delimiter is literal even if it means something in a regex
+        assert_eq!(sed(r"s|a\|b|c|", "a a|b"), Ok(String::from("a c")));
+        assert_eq!(sed(r"s/\d/#/g", "a1b2"), Ok(String::from("a#b#")));
+    }
+
+    #[test]
+    fn nth_occurrence() {
+        assert_eq!(sed("s/a/b/2", "banana"), Ok(String::from("banbna")));
+        assert_eq!(sed("s/a/b/2g", "banana"), Ok(String::from("banbnb")));
+        assert_eq!(sed("s/a/b/g2", "banana"), Ok(String::from("banbnb")));
+        assert_eq!(sed("s/a/b/4", "banana"), Err(ErrorKind::NoMatch));
+    }
+
+    #[test]
+    fn chained_commands() {
+        assert_eq!(sed("s/a/o/; s/n/m/g", "banana"), Ok(String::from("bomama")));
+        assert_eq!(sed("s/a/o/;y/bn/BN/", "banana"), Ok(String::from("BoNaNa")));
+        // Later commands which do not match change nothing
+        assert_eq!(sed("s/a/o/; s/z/q/", "banana"), Ok(String::from("bonana")));
+        // The first command decides which message is edited
+        assert_eq!(sed("s/z/q/; s/a/o/", "banana"), Err(ErrorKind::NoMatch));
+    }
+
+    #[test]
+    fn transliteration() {
+        assert_eq!(sed("y/abc/xyz/", "aabbcc d"), Ok(String::from("xxyyzz d")));
+        assert_eq!(sed("y/abc/xyz/", "def"), Err(ErrorKind::NoMatch));
+        assert_eq!(
+            sed("y/abc/xy/", "abc"),
+            Err(ErrorKind::InvalidTransliteration)
+        );
+        assert_eq!(
+            sed("y/ab/xyz/", "abc"),
+            Err(ErrorKind::InvalidTransliteration)
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        assert_eq!(sed("s/a/b/q", "abc"), Err(ErrorKind::InvalidFlags));
+        assert_eq!(sed("s/a/b/0", "abc"), Err(ErrorKind::InvalidFlags));
+        assert_eq!(sed("s/(/b/", "abc"), Err(ErrorKind::InvalidRegex));
+
+        for line in &[
+            "hello",
+            "s/a/b",
+            "s/a/",
+            "s//b/",
+            r"s\a\b\",
+            "sxaxbx",
+            "y/a/b/g",
+            "s/a/b/;",
+            "s/a/b/ t/a/b/",
+        ] {
+            assert_eq!(parse_commands(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn addressed_commands() {
+        let (target, commands) = parse("alice: s/a/b/").unwrap();
+        assert_eq!(target, Some("alice"));
+        assert_eq!(
+            commands,
+            vec![RawCommand {
+                kind: 's',
+                first: String::from("a"),
+                second: String::from("b"),
+                flags: String::new(),
+            }]
+        );
+
+        assert_eq!(parse("alice: hello"), None);
+    }
+}
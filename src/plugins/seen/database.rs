@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+#[cfg(feature = "mysql")]
+use std::sync::Arc;
+
+#[cfg(feature = "mysql")]
+use diesel::mysql::MysqlConnection;
+#[cfg(feature = "mysql")]
+use diesel::prelude::*;
+#[cfg(feature = "mysql")]
+use failure::ResultExt;
+#[cfg(feature = "mysql")]
+use r2d2::Pool;
+#[cfg(feature = "mysql")]
+use r2d2_diesel::ConnectionManager;
+
+use chrono::NaiveDateTime;
+
+use super::error::*;
+
+/// The last thing a user was seen doing.
+///
+/// `nick` is casemapped so it can be used for lookups while `display_nick`
+/// keeps the spelling the user chose.
+/// Channels are empty for activities that are not bound to a channel, like quits.
+#[cfg_attr(feature = "mysql", derive(Queryable, Insertable))]
+#[cfg_attr(feature = "mysql", table_name = "seen")]
+#[derive(PartialEq, Clone, Debug)]
+pub struct Sighting {
+    pub nick: String,
+    pub display_nick: String,
+    pub channel: String,
+    pub private: bool,
+    pub activity: String,
+    pub content: String,
+    pub time: NaiveDateTime,
+}
+
+pub trait Database: Send + Sync {
+    fn insert_sighting(&mut self, sighting: &Sighting) -> Result<(), SeenError>;
+    fn get_sighting(&self, nick: &str) -> Result<Sighting, SeenError>;
+}
+
+// HashMap
+impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Sighting, S> {
+    fn insert_sighting(&mut self, sighting: &Sighting) -> Result<(), SeenError> {
+        self.insert(sighting.nick.clone(), sighting.clone());
+
+        Ok(())
+    }
+
+    fn get_sighting(&self, nick: &str) -> Result<Sighting, SeenError> {
+        Ok(self.get(nick).cloned().ok_or(ErrorKind::NotFound)?)
+    }
+}
+
+// Diesel automatically defines the seen module as public.
+// We create a schema module to keep it private.
+#[cfg(feature = "mysql")]
+mod schema {
+    table! {
+        seen (nick) {
+            nick -> Varchar,
+            display_nick -> Varchar,
+            channel -> Varchar,
+            private -> Bool,
+            activity -> Varchar,
+            content -> Text,
+            time -> Timestamp,
+        }
+    }
+}
+
+#[cfg(feature = "mysql")]
+use self::schema::seen;
+
+#[cfg(feature = "mysql")]
+impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
+    fn insert_sighting(&mut self, sighting: &Sighting) -> Result<(), SeenError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        diesel::replace_into(seen::table)
+            .values(sighting)
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn get_sighting(&self, nick: &str) -> Result<Sighting, SeenError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        match seen::table.find(nick).first(conn) {
+            Ok(sighting) => Ok(sighting),
+            Err(diesel::NotFound) => Err(ErrorKind::NotFound)?,
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+    }
+}
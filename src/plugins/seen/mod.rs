@@ -0,0 +1,385 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use antidote::RwLock;
+use irc::client::prelude::*;
+
+use chrono::NaiveDateTime;
+use humantime::format_duration;
+use time;
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+pub mod database;
+use self::database::{Database, Sighting};
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::{Fail, ResultExt};
+use log::debug;
+
+use frippy_derive::PluginName;
+
+/// The rules a network uses to decide whether two nicks are equal.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Casemapping {
+    Ascii,
+    Rfc1459,
+    StrictRfc1459,
+}
+
+impl Casemapping {
+    fn from_isupport(value: &str) -> Option<Self> {
+        match value {
+            "ascii" => Some(Casemapping::Ascii),
+            "rfc1459" => Some(Casemapping::Rfc1459),
+            "strict-rfc1459" => Some(Casemapping::StrictRfc1459),
+            _ => None,
+        }
+    }
+
+    fn lowercase(self, name: &str) -> String {
+        name.chars()
+            .map(|c| match (self, c) {
+                (Casemapping::Rfc1459, '^') => '~',
+                (Casemapping::Rfc1459, '[')
+                | (Casemapping::Rfc1459, ']')
+                | (Casemapping::Rfc1459, '\\')
+                | (Casemapping::StrictRfc1459, '[')
+                | (Casemapping::StrictRfc1459, ']')
+                | (Casemapping::StrictRfc1459, '\\') => (c as u8 + 32) as char,
+                _ => c.to_ascii_lowercase(),
+            })
+            .collect()
+    }
+}
+
+fn get_time() -> NaiveDateTime {
+    let tm = time::now().to_timespec();
+    NaiveDateTime::from_timestamp_opt(tm.sec, 0u32).unwrap()
+}
+
+#[derive(PluginName)]
+pub struct Seen<T: Database, C> {
+    sightings: Arc<RwLock<T>>,
+    casemapping: RwLock<Casemapping>,
+    /// Casemapped names of channels with the secret or private mode
+    private_channels: RwLock<HashSet<String>>,
+    /// Channels which are always treated as private
+    configured_private: Vec<String>,
+    phantom: PhantomData<C>,
+}
+
+impl<T: Database, C: FrippyClient> Seen<T, C> {
+    /// Creates the plugin, activity in `private_channels` is never revealed
+    /// outside of the channel it happened in.
+    pub fn new(db: T, private_channels: &[&str]) -> Self {
+        Seen {
+            sightings: Arc::new(RwLock::new(db)),
+            casemapping: RwLock::new(Casemapping::Rfc1459),
+            private_channels: RwLock::new(HashSet::new()),
+            configured_private: private_channels.iter().map(|&c| c.to_owned()).collect(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.sightings)
+    }
+
+    fn lowercase(&self, name: &str) -> String {
+        self.casemapping.read().lowercase(name)
+    }
+
+    fn is_private(&self, channel: &str) -> bool {
+        let channel = self.lowercase(channel);
+
+        self.private_channels.read().contains(&channel)
+            || self
+                .configured_private
+                .iter()
+                .any(|c| self.lowercase(c) == channel)
+    }
+
+    fn record(
+        &self,
+        nick: &str,
+        channel: &str,
+        activity: &str,
+        content: &str,
+    ) -> Result<(), SeenError> {
+        let sighting = Sighting {
+            nick: self.lowercase(nick),
+            display_nick: nick.to_owned(),
+            channel: channel.to_owned(),
+            private: !channel.is_empty() && self.is_private(channel),
+            activity: activity.to_owned(),
+            content: content.to_owned(),
+            time: get_time(),
+        };
+
+        debug!("Saving sighting {:?}", sighting);
+        self.sightings.write().insert_sighting(&sighting)
+    }
+
+    /// Remembers whether a channel is secret or private from its modes.
+    fn set_modes(&self, channel: &str, modes: &str) {
+        let channel = self.lowercase(channel);
+        let mut private_channels = self.private_channels.write();
+
+        if modes.starts_with('+') && modes.contains(|c| c == 's' || c == 'p') {
+            private_channels.insert(channel);
+        } else {
+            private_channels.remove(&channel);
+        }
+    }
+
+    fn on_message(&self, client: &C, message: &Message) -> Result<(), SeenError> {
+        let nick = message.source_nickname().unwrap_or("");
+
+        match message.command {
+            Command::PRIVMSG(ref target, ref content) => {
+                // Private messages are nobody else's business
+                if target.starts_with('#') || target.starts_with('&') {
+                    self.record(nick, target, "message", content)?;
+                }
+            }
+            Command::JOIN(ref channel, _, _) => {
+                if nick == client.current_nickname() {
+                    // The reply tells us whether the channel is secret
+                    client
+                        .send(Command::ChannelMODE(channel.clone(), Vec::new()))
+                        .context(ErrorKind::Connection)?;
+                } else {
+                    self.record(nick, channel, "join", "")?;
+                }
+            }
+            Command::PART(ref channel, ref reason) => {
+                let reason = reason.as_ref().map_or("", String::as_str);
+                self.record(nick, channel, "part", reason)?;
+            }
+            Command::QUIT(ref reason) => {
+                let reason = reason.as_ref().map_or("", String::as_str);
+                self.record(nick, "", "quit", reason)?;
+            }
+            Command::NICK(ref new_nick) => {
+                self.record(nick, "", "nick", new_nick)?;
+            }
+            Command::ChannelMODE(ref channel, ref modes) => {
+                // Ask for the complete modes instead of applying every change
+                if !modes.is_empty() {
+                    client
+                        .send(Command::ChannelMODE(channel.clone(), Vec::new()))
+                        .context(ErrorKind::Connection)?;
+                }
+            }
+            Command::Response(Response::RPL_CHANNELMODEIS, ref args, ref suffix) => {
+                if let Some(channel) = args.get(1) {
+                    let modes = args.get(2).or_else(|| suffix.as_ref());
+                    self.set_modes(channel, modes.map_or("", String::as_str));
+                }
+            }
+            Command::Response(Response::RPL_ISUPPORT, ref args, _) => {
+                let casemapping = args
+                    .iter()
+                    .filter_map(|a| a.strip_prefix("CASEMAPPING="))
+                    .filter_map(Casemapping::from_isupport)
+                    .next();
+
+                if let Some(casemapping) = casemapping {
+                    *self.casemapping.write() = casemapping;
+                }
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn seen(&self, client: &C, command: &PluginCommand) -> Result<String, SeenError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let nick = self.lowercase(&command.tokens[0]);
+        if nick == self.lowercase(&command.source) {
+            return Ok(String::from("That's you!"));
+        }
+        if nick == self.lowercase(client.current_nickname()) {
+            return Ok(String::from("I'm right here."));
+        }
+
+        let sighting = self.sightings.read().get_sighting(&nick)?;
+
+        let now = Duration::new(time::now().to_timespec().sec as u64, 0);
+        let dur = now - Duration::new(sighting.time.timestamp() as u64, 0);
+        let when = format!(
+            "{} was last seen {} ago",
+            sighting.display_nick,
+            format_duration(dur)
+        );
+
+        // Secret channels are only revealed to the people in them
+        if sighting.private && self.lowercase(&sighting.channel) != self.lowercase(&command.target)
+        {
+            return Ok(format!("{} in a secret channel.", when));
+        }
+
+        let reason = |reason: &str| {
+            if reason.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", reason)
+            }
+        };
+
+        Ok(match sighting.activity.as_ref() {
+            "message" => format!(
+                "{} in {} saying: {}",
+                when, sighting.channel, sighting.content
+            ),
+            "join" => format!("{} joining {}.", when, sighting.channel),
+            "part" => format!(
+                "{} leaving {}{}.",
+                when,
+                sighting.channel,
+                reason(&sighting.content)
+            ),
+            "quit" => format!("{} quitting{}.", when, reason(&sighting.content)),
+            "nick" => format!("{} changing their nick to {}.", when, sighting.content),
+            _ => format!("{}.", when),
+        })
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"seen help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "Tells you when a user was last active and what they did.\r\n\
+         usage: seen <nick>"
+    }
+}
+
+impl<T: Database, C: FrippyClient> Plugin for Seen<T, C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        match self.on_message(client, message) {
+            Ok(()) => ExecutionStatus::Done,
+            Err(e) => ExecutionStatus::Err(e.context(FrippyErrorKind::Seen).into()),
+        }
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Seen should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        if command.tokens.is_empty() {
+            client
+                .send_privmsg(&command.target, self.invalid_command())
+                .context(FrippyErrorKind::Connection)?;
+            return Ok(());
+        }
+
+        if command.tokens[0] == "help" {
+            client
+                .send_privmsg(&command.target, self.help())
+                .context(FrippyErrorKind::Connection)?;
+            return Ok(());
+        }
+
+        match self.seen(client, &command) {
+            Ok(msg) => client
+                .send_privmsg(&command.target, &msg)
+                .context(FrippyErrorKind::Connection)?,
+            Err(e) => {
+                // This warning only occurs if frippy is built without a database
+                #[allow(unreachable_patterns)]
+                let message = match e.kind() {
+                    ErrorKind::NotFound => format!("I have not seen {}.", command.tokens[0]),
+                    ErrorKind::InvalidCommand => self.invalid_command().to_owned(),
+                    _ => e.to_string(),
+                };
+
+                client
+                    .send_privmsg(&command.target, &message)
+                    .context(FrippyErrorKind::Connection)?;
+
+                match e.kind() {
+                    ErrorKind::NotFound | ErrorKind::InvalidCommand => (),
+                    _ => Err(e).context(FrippyErrorKind::Seen)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from(
+            "Evaluation of commands is not implemented for Seen at this time",
+        ))
+    }
+
+    fn inspect(&self) -> String {
+        let mut private = self
+            .private_channels
+            .read()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        private.sort();
+
+        format!(
+            "Casemapping: {:?}\nPrivate channels: {}\nConfigured private channels: {}",
+            *self.casemapping.read(),
+            private.join(", "),
+            self.configured_private.join(", ")
+        )
+    }
+}
+
+impl<T: Database, C: FrippyClient> fmt::Debug for Seen<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Seen {{ ... }}")
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "SeenError"]
+    pub enum ErrorKind {
+        /// Invalid command error
+        #[fail(display = "Invalid Command")]
+        InvalidCommand,
+
+        /// Not found error
+        #[fail(display = "User was not seen")]
+        NotFound,
+
+        /// Connection error
+        #[fail(display = "Failed to send a message")]
+        Connection,
+
+        /// MySQL error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "Failed to execute MySQL Query")]
+        MysqlError,
+
+        /// No connection error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "No connection to the database")]
+        NoConnection,
+    }
+}
@@ -42,13 +42,19 @@ pub trait Database: Send + Sync {
     fn get_tells(&self, receiver: &str) -> Result<Vec<TellMessage>, TellError>;
     fn get_receivers(&self) -> Result<Vec<String>, TellError>;
     fn delete_tells(&mut self, receiver: &str) -> Result<(), TellError>;
+    fn delete_tell(&mut self, id: i64) -> Result<(), TellError>;
 }
 
 // HashMap
 impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Vec<TellMessage>, S> {
     fn insert_tell(&mut self, tell: &NewTellMessage) -> Result<(), TellError> {
+        let id = self
+            .values()
+            .flat_map(|tells| tells.iter().map(|t| t.id + 1))
+            .max()
+            .unwrap_or(0);
         let tell = TellMessage {
-            id: 0,
+            id,
             sender: tell.sender.to_string(),
             receiver: tell.receiver.to_string(),
             time: tell.time,
@@ -81,6 +87,22 @@ impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Vec
             None => Err(ErrorKind::NotFound)?,
         }
     }
+
+    fn delete_tell(&mut self, id: i64) -> Result<(), TellError> {
+        let receiver = self
+            .iter()
+            .find(|(_, tells)| tells.iter().any(|t| t.id == id))
+            .map(|(receiver, _)| receiver.clone())
+            .ok_or(ErrorKind::NotFound)?;
+
+        let tells = self.get_mut(&receiver).expect("receiver was just found");
+        tells.retain(|t| t.id != id);
+        if tells.is_empty() {
+            self.remove(&receiver);
+        }
+
+        Ok(())
+    }
 }
 
 // Diesel automatically defines the tells module as public.
@@ -147,4 +169,15 @@ impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
             .context(ErrorKind::MysqlError)?;
         Ok(())
     }
+
+    fn delete_tell(&mut self, id: i64) -> Result<(), TellError> {
+        use self::tells::columns;
+
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+        match diesel::delete(tells::table.filter(columns::id.eq(id))).execute(conn) {
+            Ok(0) => Err(ErrorKind::NotFound)?,
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context(ErrorKind::MysqlError)?,
+        }
+    }
 }
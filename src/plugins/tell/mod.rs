@@ -1,4 +1,5 @@
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use antidote::RwLock;
 use irc::client::data::User;
@@ -27,18 +28,23 @@ use self::database::Database;
 
 #[derive(PluginName)]
 pub struct Tell<T: Database, C> {
-    tells: RwLock<T>,
+    tells: Arc<RwLock<T>>,
     phantom: PhantomData<C>,
 }
 
 impl<T: Database, C: FrippyClient> Tell<T, C> {
     pub fn new(db: T) -> Self {
         Tell {
-            tells: RwLock::new(db),
+            tells: Arc::new(RwLock::new(db)),
             phantom: PhantomData,
         }
     }
 
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.tells)
+    }
+
     fn tell_command(&self, client: &C, command: PluginCommand) -> Result<String, TellError> {
         if command.tokens.len() < 2 {
             return Ok(self.invalid_command().to_owned());
@@ -253,6 +259,17 @@ impl<T: Database, C: FrippyClient> Plugin for Tell<T, C> {
     fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
         Err(String::from("This Plugin does not implement any commands."))
     }
+
+    fn inspect(&self) -> String {
+        match self.tells.read().get_receivers() {
+            Ok(ref receivers) if receivers.is_empty() => String::from("No outstanding tells"),
+            Ok(receivers) => format!(
+                "Outstanding tells for {}",
+                receivers.into_iter().unique().join(", ")
+            ),
+            Err(e) => e.to_string(),
+        }
+    }
 }
 
 use std::fmt;
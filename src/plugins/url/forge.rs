@@ -0,0 +1,298 @@
+//! Describes issues, pull requests and commits of code forges
+//! using their JSON APIs instead of their noisy page titles.
+//!
+//! The API base URLs are configurable, which makes it possible to test
+//! this with a local server as long as its host is allowed by `url_allowed_hosts`.
+use std::time::Duration;
+
+use reqwest::Url as ParsedUrl;
+use serde_json::Value;
+
+use super::error::*;
+use super::single_line;
+use crate::utils::Url;
+use failure::ResultExt;
+use log::debug;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForgeKind {
+    GitHub,
+    Gitea,
+    GitLab,
+}
+
+impl ForgeKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_ref() {
+            "github" => Some(ForgeKind::GitHub),
+            "gitea" | "forgejo" => Some(ForgeKind::Gitea),
+            "gitlab" => Some(ForgeKind::GitLab),
+            _ => None,
+        }
+    }
+}
+
+/// A forge whose links are posted on `host`, which may include a port,
+/// and whose API is found at `api`.
+#[derive(Clone, Debug)]
+pub struct Forge {
+    pub host: String,
+    pub kind: ForgeKind,
+    pub api: String,
+}
+
+impl Forge {
+    pub fn new(host: &str, kind: ForgeKind, api: &str) -> Self {
+        Forge {
+            host: host.to_lowercase(),
+            kind,
+            api: api.trim_end_matches('/').to_owned(),
+        }
+    }
+}
+
+/// The public forges which are known without any configuration.
+pub fn default_forges() -> Vec<Forge> {
+    vec![
+        Forge::new("github.com", ForgeKind::GitHub, "https://api.github.com"),
+        Forge::new("gitlab.com", ForgeKind::GitLab, "https://gitlab.com/api/v4"),
+        Forge::new(
+            "codeberg.org",
+            ForgeKind::Gitea,
+            "https://codeberg.org/api/v1",
+        ),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Item {
+    Issue(u64),
+    PullRequest(u64),
+    Commit,
+}
+
+/// A link to an issue, pull request or commit and the API endpoint describing it.
+#[derive(Clone, Debug)]
+pub struct Link {
+    kind: ForgeKind,
+    item: Item,
+    endpoint: String,
+}
+
+/// Recognizes links to issues, pull requests and commits of `forges`.
+pub fn find_link(forges: &[Forge], url: &str) -> Option<Link> {
+    let parsed = ParsedUrl::parse(url).ok()?;
+    let host = parsed.host_str()?.to_lowercase();
+    let authority = match parsed.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    };
+    let forge = forges.iter().find(|f| f.host == authority)?;
+
+    let segments = parsed
+        .path_segments()?
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    let (project, kind, id) = match forge.kind {
+        ForgeKind::GitHub | ForgeKind::Gitea => match *segments.as_slice() {
+            [owner, repo, kind, id] => (format!("{}/{}", owner, repo), kind, id),
+            _ => return None,
+        },
+        // Projects can be nested in groups and are separated from the rest by "-"
+        ForgeKind::GitLab => {
+            let dash = segments.iter().position(|&s| s == "-")?;
+            match segments[dash + 1..] {
+                [kind, id] if dash > 0 => (segments[..dash].join("/"), kind, id),
+                _ => return None,
+            }
+        }
+    };
+
+    let item = match (forge.kind, kind) {
+        (ForgeKind::GitHub, "issues")
+        | (ForgeKind::Gitea, "issues")
+        | (ForgeKind::GitLab, "issues") => Item::Issue(id.parse().ok()?),
+        (ForgeKind::GitHub, "pull")
+        | (ForgeKind::Gitea, "pulls")
+        | (ForgeKind::GitLab, "merge_requests") => Item::PullRequest(id.parse().ok()?),
+        (_, "commit") if id.chars().all(|c| c.is_ascii_hexdigit()) => Item::Commit,
+        _ => return None,
+    };
+
+    let endpoint = match (forge.kind, &item) {
+        (ForgeKind::GitHub, Item::Issue(n)) | (ForgeKind::Gitea, Item::Issue(n)) => {
+            format!("{}/repos/{}/issues/{}", forge.api, project, n)
+        }
+        (ForgeKind::GitHub, Item::PullRequest(n)) | (ForgeKind::Gitea, Item::PullRequest(n)) => {
+            format!("{}/repos/{}/pulls/{}", forge.api, project, n)
+        }
+        (ForgeKind::GitHub, Item::Commit) => {
+            format!("{}/repos/{}/commits/{}", forge.api, project, id)
+        }
+        (ForgeKind::Gitea, Item::Commit) => {
+            format!("{}/repos/{}/git/commits/{}", forge.api, project, id)
+        }
+        (ForgeKind::GitLab, _) => {
+            let project = project.replace('/', "%2F");
+            match item {
+                Item::Issue(n) => format!("{}/projects/{}/issues/{}", forge.api, project, n),
+                Item::PullRequest(n) => {
+                    format!("{}/projects/{}/merge_requests/{}", forge.api, project, n)
+                }
+                Item::Commit => {
+                    format!(
+                        "{}/projects/{}/repository/commits/{}",
+                        forge.api, project, id
+                    )
+                }
+            }
+        }
+    };
+
+    Some(Link {
+        kind: forge.kind,
+        item,
+        endpoint,
+    })
+}
+
+fn plural(count: u64, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+fn first_line(text: &str) -> String {
+    single_line(text.lines().next().unwrap_or(""))
+}
+
+/// Describes a link like "#123 [open] Fix crash in parser — alice, 4 comments".
+pub fn describe(network: &str, link: &Link, max_kib: usize) -> Result<String, UrlError> {
+    debug!("Requesting {}", link.endpoint);
+
+    let body = Url::from(link.endpoint.as_str())
+        .max_kib(max_kib)
+        .timeout(Duration::from_secs(5))
+        .network(network)
+        .request()
+        .context(ErrorKind::Download)?;
+    let json: Value = serde_json::from_str(&body).context(ErrorKind::InvalidResponse)?;
+
+    describe_json(link, &json)
+}
+
+fn describe_json(link: &Link, json: &Value) -> Result<String, UrlError> {
+    match link.item {
+        Item::Issue(number) | Item::PullRequest(number) => {
+            let title = json["title"].as_str().ok_or(ErrorKind::InvalidResponse)?;
+            let state = match (json["state"].as_str(), json["merged"].as_bool()) {
+                (_, Some(true)) => "merged",
+                (Some("opened"), _) => "open",
+                (Some(state), _) => state,
+                (None, _) => Err(ErrorKind::InvalidResponse)?,
+            };
+            let author = json["user"]["login"]
+                .as_str()
+                .or_else(|| json["author"]["username"].as_str())
+                .map_or_else(|| String::from("unknown"), single_line);
+            let comments = json["comments"]
+                .as_u64()
+                .or_else(|| json["user_notes_count"].as_u64())
+                .unwrap_or(0);
+
+            // Merge requests have their own sigil on GitLab
+            let sigil = match (link.kind, &link.item) {
+                (ForgeKind::GitLab, Item::PullRequest(_)) => '!',
+                _ => '#',
+            };
+
+            Ok(format!(
+                "{}{} [{}] {} — {}, {}",
+                sigil,
+                number,
+                single_line(state),
+                first_line(title),
+                author,
+                plural(comments, "comment")
+            ))
+        }
+        Item::Commit => {
+            let sha = json["sha"]
+                .as_str()
+                .or_else(|| json["id"].as_str())
+                .ok_or(ErrorKind::InvalidResponse)?;
+            let message = json["commit"]["message"]
+                .as_str()
+                .or_else(|| json["message"].as_str())
+                .ok_or(ErrorKind::InvalidResponse)?;
+            let author = json["author"]["login"]
+                .as_str()
+                .or_else(|| json["commit"]["author"]["name"].as_str())
+                .or_else(|| json["author_name"].as_str())
+                .map_or_else(|| String::from("unknown"), single_line);
+
+            Ok(format!(
+                "{} {} — {}",
+                single_line(&sha.chars().take(7).collect::<String>()),
+                first_line(message),
+                author
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn describe(url: &str, json: &Value) -> String {
+        let link = find_link(&default_forges(), url).expect("url should be a forge link");
+        describe_json(&link, json).unwrap()
+    }
+
+    #[test]
+    fn issues() {
+        let json = json!({
+            "title": "Fix crash in parser\nDetails follow",
+            "state": "open",
+            "user": {"login": "alice"},
+            "comments": 4,
+        });
+        assert_eq!(
+            describe("https://github.com/a/b/issues/123", &json),
+            "#123 [open] Fix crash in parser — alice, 4 comments"
+        );
+
+        let json = json!({
+            "title": "Add a feature",
+            "state": "closed\r\nQUIT",
+            "merged": false,
+            "author": {"username": "bob\rQUIT\n"},
+            "user_notes_count": 1,
+        });
+        assert_eq!(
+            describe("https://gitlab.com/g/p/-/merge_requests/7", &json),
+            "!7 [closed||QUIT] Add a feature — bob|QUIT, 1 comment"
+        );
+    }
+
+    #[test]
+    fn commits() {
+        let json = json!({
+            "sha": "0123456789abcdef",
+            "commit": {
+                "message": "Fix a bug\r\n\nLonger description",
+                "author": {"name": "Carol\nQUIT"},
+            },
+            "author": null,
+        });
+        assert_eq!(
+            describe("https://github.com/a/b/commit/0123456789abcdef", &json),
+            "0123456 Fix a bug — Carol|QUIT"
+        );
+    }
+}
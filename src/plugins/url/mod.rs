@@ -0,0 +1,846 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use antidote::RwLock;
+use irc::client::prelude::*;
+use reqwest::Url as ParsedUrl;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use scraper::{Html, Selector};
+
+use crate::metrics;
+use crate::plugin::*;
+use crate::plugins::urls::{self, database::Database as History};
+use crate::utils::{Response, Url};
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::Fail;
+use failure::ResultExt;
+use log::{debug, error};
+
+use frippy_derive::PluginName;
+
+pub mod forge;
+use self::forge::{Forge, ForgeKind, Link};
+pub mod oembed;
+use self::oembed::Provider;
+
+/// Bytes of files which are read to describe them,
+/// images can have a lot of metadata before their dimensions.
+const PREVIEW_BYTES: usize = 64 * 1024;
+
+/// First posters which are remembered, the oldest are forgotten first.
+const MAX_POSTERS: usize = 10_000;
+
+lazy_static! {
+    pub(crate) static ref URL_RE: Regex = Regex::new(r"(^|\s)(https?://\S+)").unwrap();
+    static ref WORD_RE: Regex = Regex::new(r"(\w+)").unwrap();
+    static ref TITLE_SELECTOR: Selector = Selector::parse("title").unwrap();
+    static ref META_SELECTOR: Selector = Selector::parse("meta").unwrap();
+}
+
+/// How the links of a domain are treated.
+#[derive(Clone, Default, Debug)]
+pub struct DomainRule {
+    /// Never send the titles of this domain
+    pub ignore: bool,
+    /// Send titles even if they do not seem useful
+    pub always_show: bool,
+    /// A CSS selector for the element whose text is the title
+    pub selector: Option<String>,
+    /// Sent in front of the titles instead of "[URL]"
+    pub prefix: Option<String>,
+}
+
+impl FromStr for DomainRule {
+    type Err = UrlError;
+
+    /// Parses rules like "show; prefix=[Wiki]; selector=h1",
+    /// "ignore" or an empty string for the default behaviour.
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let mut parsed = DomainRule::default();
+
+        for part in rule.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut split = part.splitn(2, '=');
+            let key = split.next().unwrap().trim();
+            let value = split.next().map(str::trim);
+
+            match (key, value) {
+                ("ignore", None) => parsed.ignore = true,
+                ("show", None) => parsed.always_show = true,
+                ("selector", Some(selector)) => {
+                    if Selector::parse(selector).is_err() {
+                        Err(ErrorKind::InvalidRule)?;
+                    }
+                    parsed.selector = Some(selector.to_owned());
+                }
+                ("prefix", Some(prefix)) => parsed.prefix = Some(prefix.to_owned()),
+                _ => Err(ErrorKind::InvalidRule)?,
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl DomainRule {
+    /// Titles differ between rules so they are cached separately.
+    fn cache_key(&self, url: &str) -> String {
+        let mut key = normalize(url);
+        if self.always_show {
+            key.push_str(" show");
+        }
+        if let Some(ref selector) = self.selector {
+            key.push(' ');
+            key.push_str(selector);
+        }
+
+        key
+    }
+}
+
+/// Returns true if `host` is `domain` or one of its subdomains,
+/// "*" matches every host.
+fn matches_domain(host: &str, domain: &str) -> bool {
+    domain == "*"
+        || host == domain
+        || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+#[derive(PluginName)]
+pub struct UrlTitles<C> {
+    max_kib: usize,
+    max_urls: usize,
+    cache_ttl: Duration,
+    track_posters: bool,
+    /// Rules by channel, or all channels, and domain
+    rules: Vec<(Option<String>, String, DomainRule)>,
+    forges: Vec<Forge>,
+    oembed_providers: Vec<Provider>,
+    /// The URLs stored by the `Urls` plugin whose titles are filled in
+    history: Option<Arc<RwLock<dyn History>>>,
+    /// Titles by normalized URL and when they were fetched
+    cache: RwLock<HashMap<String, (Instant, String)>>,
+    /// Who first posted a normalized URL in a channel and when
+    posters: RwLock<HashMap<(String, String), (String, Instant)>>,
+    phantom: PhantomData<C>,
+}
+
+/// A title which is either cached or still being downloaded.
+enum Pending {
+    Cached(String),
+    Fetching(String, JoinHandle<Result<String, UrlError>>),
+}
+
+/// Makes URLs which lead to the same page compare equal by removing
+/// fragments and tracking parameters.
+fn normalize(url: &str) -> String {
+    let mut parsed = match ParsedUrl::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_owned(),
+    };
+
+    parsed.set_fragment(None);
+
+    let query = parsed
+        .query_pairs()
+        .filter(|(key, _)| !key.starts_with("utm_"))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    parsed.into_string()
+}
+
+/// Formats a duration using its largest unit, like "3 days".
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (count, unit) = match secs {
+        0..=59 => (secs, "second"),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86_399 => (secs / 3600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+
+    if count == 1 {
+        format!("{} {}", count, unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+/// Puts text from a page or an API on a single line
+/// so it can't send extra IRC commands.
+fn single_line(text: &str) -> String {
+    text.trim().replace(['\n', '\r'], "|")
+}
+
+#[derive(Clone, Debug)]
+struct Title(String, Option<usize>);
+
+impl From<String> for Title {
+    fn from(title: String) -> Self {
+        Title(title, None)
+    }
+}
+
+impl From<Title> for String {
+    fn from(title: Title) -> Self {
+        title.0
+    }
+}
+
+impl Title {
+    /// Finds the content of a `<meta>` tag by its `property` or `name`.
+    fn find_meta(document: &Html, name: &str) -> Option<Self> {
+        let title = document
+            .select(&META_SELECTOR)
+            .filter(|meta| {
+                let element = meta.value();
+                element
+                    .attr("property")
+                    .or_else(|| element.attr("name"))
+                    .map_or(false, |p| p.eq_ignore_ascii_case(name))
+            })
+            .filter_map(|meta| meta.value().attr("content"))
+            .next()?;
+
+        debug!("Found title {:?} in meta tag {:?}", title, name);
+
+        Some(title.to_owned().into())
+    }
+
+    fn find_title(document: &Html) -> Option<Self> {
+        let title = document
+            .select(&TITLE_SELECTOR)
+            .next()?
+            .text()
+            .collect::<String>();
+
+        debug!("Found title {:?}", title);
+
+        Some(title.into())
+    }
+
+    // TODO Improve logic
+    fn get_usefulness(self, url: &str) -> Self {
+        let mut usefulness = 0;
+        for word in WORD_RE.find_iter(&self.0) {
+            let w = word.as_str().to_lowercase();
+            if w.len() > 2 && !url.to_lowercase().contains(&w) {
+                usefulness += 1;
+            }
+        }
+
+        Title(self.0, Some(usefulness))
+    }
+
+    pub fn usefulness(&self) -> usize {
+        self.1.expect("Usefulness should be calculated already")
+    }
+
+    fn clean_up(self) -> Self {
+        Title(single_line(&self.0), self.1)
+    }
+
+    /// Returns the titles of a page, the preferred ones first.
+    pub fn find_clean_titles(document: &Html, url: &str) -> Vec<Self> {
+        vec![
+            Self::find_meta(document, "og:title"),
+            Self::find_meta(document, "twitter:title"),
+            Self::find_title(document),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|title| title.get_usefulness(url).clean_up())
+        .collect()
+    }
+
+    pub fn find_clean_description(document: &Html, url: &str) -> Option<Self> {
+        Self::find_meta(document, "og:description")
+            .map(|description| description.get_usefulness(url).clean_up())
+            .filter(|description| !description.0.is_empty())
+    }
+}
+
+impl<C: FrippyClient> UrlTitles<C> {
+    /// If a file is larger than `max_kib` KiB the download is stopped
+    pub fn new(max_kib: usize) -> Self {
+        UrlTitles {
+            max_kib,
+            max_urls: 1,
+            cache_ttl: Duration::from_secs(0),
+            track_posters: false,
+            rules: Vec::new(),
+            forges: forge::default_forges(),
+            oembed_providers: oembed::default_providers(),
+            history: None,
+            cache: RwLock::new(HashMap::new()),
+            posters: RwLock::new(HashMap::new()),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Reuses titles which were fetched less than `ttl` ago.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Mentions who first posted a URL in a channel when it is posted again.
+    pub fn track_posters(mut self, track: bool) -> Self {
+        self.track_posters = track;
+        self
+    }
+
+    /// Titles are fetched for at most `max_urls` links of a message.
+    pub fn max_urls(mut self, max_urls: usize) -> Self {
+        self.max_urls = max_urls;
+        self
+    }
+
+    /// Applies `rule` to links of `domain` and its subdomains.
+    /// Rules of a channel are preferred over rules without a channel,
+    /// more specific domains are preferred over less specific ones.
+    pub fn rule(mut self, channel: Option<&str>, domain: &str, rule: DomainRule) -> Self {
+        self.rules
+            .push((channel.map(str::to_lowercase), domain.to_lowercase(), rule));
+        self
+    }
+
+    /// Describes issues, pull requests and commits linked on `host`
+    /// using the API at `api`, known forges are replaced.
+    pub fn forge(mut self, host: &str, kind: ForgeKind, api: &str) -> Self {
+        let forge = Forge::new(host, kind, api);
+        self.forges.retain(|f| f.host != forge.host);
+        self.forges.push(forge);
+        self
+    }
+
+    /// Describes links matching one of `schemes` with the oEmbed `endpoint`
+    /// instead of the page they lead to.
+    pub fn oembed_provider(mut self, endpoint: &str, schemes: &[&str]) -> Self {
+        self.oembed_providers.push(Provider::new(endpoint, schemes));
+        self
+    }
+
+    /// Adds the titles which were found to the URLs stored by the `Urls` plugin
+    /// so they can be searched too.
+    pub fn history(mut self, history: Arc<RwLock<dyn History>>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Adds titles to the history, ignored and failed links keep none.
+    fn remember(&self, channel: &str, titles: &[(&str, &str)]) {
+        let history = match self.history {
+            Some(ref history) => history,
+            None => return,
+        };
+
+        for &(url, title) in titles {
+            if let Err(e) = urls::set_title(&**history, channel, url, title) {
+                error!("Failed to store the title of {}: {}", url, e);
+            }
+        }
+    }
+
+    /// Finds the rule for a link posted in `channel`.
+    fn rule_for(&self, channel: &str, url: &str) -> DomainRule {
+        let host = match ParsedUrl::parse(url) {
+            Ok(ref parsed) => parsed.host_str().unwrap_or("").to_lowercase(),
+            Err(_) => return DomainRule::default(),
+        };
+        let channel = channel.to_lowercase();
+
+        self.rules
+            .iter()
+            .filter(|(c, _, _)| c.as_ref().map_or(true, |c| *c == channel))
+            .filter(|(_, domain, _)| matches_domain(&host, domain))
+            .max_by_key(|(c, domain, _)| (c.is_some(), domain != "*", domain.len()))
+            .map(|(_, _, rule)| rule.clone())
+            .unwrap_or_default()
+    }
+
+    fn grep_urls<'a>(&self, msg: &'a str) -> Vec<&'a str> {
+        let mut urls = Vec::new();
+
+        for captures in URL_RE.captures_iter(msg) {
+            debug!("Url captures: {:?}", captures);
+            if urls.len() >= self.max_urls {
+                break;
+            }
+
+            let url = captures.get(2).unwrap().as_str();
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+
+        urls
+    }
+
+    /// Remembers who posted a URL first and returns a note
+    /// if somebody else did so before.
+    ///
+    /// Posters are remembered even if the title could not be fetched
+    /// because the link was posted anyway.
+    fn first_poster(&self, channel: &str, nick: &str, url: &str) -> Option<String> {
+        if !self.track_posters {
+            return None;
+        }
+
+        let key = (channel.to_lowercase(), normalize(url));
+        let mut posters = self.posters.write();
+        match posters.get(&key) {
+            Some(&(ref poster, time)) if !poster.eq_ignore_ascii_case(nick) => Some(format!(
+                "(first posted by {} {} ago)",
+                poster,
+                format_age(time.elapsed())
+            )),
+            Some(_) => None,
+            None => {
+                if posters.len() >= MAX_POSTERS {
+                    let oldest = posters
+                        .iter()
+                        .min_by_key(|(_, &(_, time))| time)
+                        .map(|(key, _)| key.clone());
+                    if let Some(oldest) = oldest {
+                        posters.remove(&oldest);
+                    }
+                }
+
+                posters.insert(key, (nick.to_owned(), Instant::now()));
+                None
+            }
+        }
+    }
+
+    fn cached_title(&self, key: &str) -> Option<String> {
+        match self.cache.read().get(key) {
+            Some(&(time, ref title)) if time.elapsed() < self.cache_ttl => {
+                debug!("Using cached title for {}", key);
+                Some(title.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the titles of the URLs in the same order.
+    /// Titles which are not cached are fetched at the same time.
+    fn titles(&self, network: &str, urls: &[(&str, DomainRule)]) -> Vec<Result<String, UrlError>> {
+        let max_kib = self.max_kib;
+        let pending = urls
+            .iter()
+            .map(|&(url, ref rule)| {
+                let key = rule.cache_key(url);
+                match self.cached_title(&key) {
+                    Some(title) => Pending::Cached(title),
+                    None => {
+                        let url = url.to_owned();
+                        let rule = rule.clone();
+                        let network = network.to_owned();
+                        let link = forge::find_link(&self.forges, &url);
+                        let endpoint = oembed::find_endpoint(&self.oembed_providers, &url);
+                        Pending::Fetching(
+                            key,
+                            thread::spawn(move || {
+                                fetch(&network, &url, max_kib, &rule, link, endpoint)
+                            }),
+                        )
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut titles = Vec::with_capacity(pending.len());
+        for fetch in pending {
+            let (key, handle) = match fetch {
+                Pending::Cached(title) => {
+                    titles.push(Ok(title));
+                    continue;
+                }
+                Pending::Fetching(key, handle) => (key, handle),
+            };
+
+            let title = handle
+                .join()
+                .unwrap_or_else(|_| Err(ErrorKind::Download.into()));
+
+            if let Ok(ref title) = title {
+                if self.cache_ttl > Duration::from_secs(0) {
+                    let mut cache = self.cache.write();
+                    let ttl = self.cache_ttl;
+                    cache.retain(|_, &mut (time, _)| time.elapsed() < ttl);
+                    cache.insert(key, (Instant::now(), title.clone()));
+                }
+            }
+
+            titles.push(title);
+        }
+
+        titles
+    }
+
+    fn url(&self, network: &str, channel: &str, text: &str) -> Result<String, UrlError> {
+        let url = *self.grep_urls(text).first().ok_or(ErrorKind::MissingUrl)?;
+        let rule = self.rule_for(channel, url);
+        if rule.ignore {
+            Err(ErrorKind::Ignored)?;
+        }
+
+        self.titles(network, &[(url, rule)]).remove(0)
+    }
+}
+
+/// Guesses the type of a file from its first bytes.
+fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    let mime_type = match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "video/webm",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [b'I', b'D', b'3', ..] => "audio/mpeg",
+        [b'%', b'P', b'D', b'F', b'-', ..] => "application/pdf",
+        [b'P', b'K', 0x03, 0x04, ..] => "application/zip",
+        [0x1F, 0x8B, ..] => "application/gzip",
+        _ => return None,
+    };
+
+    Some(mime_type)
+}
+
+/// Formats a number of bytes like "2.3 MiB".
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Describes files which are not web pages by their type, size
+/// and the dimensions of images.
+fn describe_file(response: &Response) -> Option<String> {
+    let declared = response
+        .mime_type()
+        .filter(|&mime_type| mime_type != "application/octet-stream");
+
+    let mime_type = match declared {
+        Some(_) if response.is_markup() => return None,
+        Some(mime_type) => mime_type,
+        None => sniff_mime_type(&response.bytes)?,
+    };
+
+    let mut parts = vec![mime_type.to_owned()];
+    if mime_type.starts_with("image/") {
+        if let Ok(size) = imagesize::blob_size(&response.bytes) {
+            parts.push(format!("{}x{}", size.width, size.height));
+        }
+    }
+    if let Some(length) = response.content_length() {
+        parts.push(format_size(length));
+    }
+
+    Some(parts.join(", "))
+}
+
+/// Finds the text of the first element matching `selector`.
+fn find_selected(document: &Html, selector: &str) -> Option<Title> {
+    let selector = Selector::parse(selector).ok()?;
+    let title = document
+        .select(&selector)
+        .next()?
+        .text()
+        .collect::<String>();
+
+    debug!("Found title {:?} with a custom selector", title);
+
+    Some(Title::from(title).clean_up()).filter(|title| !title.0.is_empty())
+}
+
+/// Describes a link with the API of its forge or its oEmbed endpoint
+/// and falls back to the title of the page if they fail.
+fn fetch(
+    network: &str,
+    url: &str,
+    max_kib: usize,
+    rule: &DomainRule,
+    link: Option<Link>,
+    endpoint: Option<String>,
+) -> Result<String, UrlError> {
+    if let Some(link) = link {
+        match forge::describe(network, &link, max_kib) {
+            Ok(description) => return Ok(description),
+            Err(e) => debug!("Failed to describe forge link: {}", e),
+        }
+    }
+
+    if let Some(endpoint) = endpoint {
+        match oembed::describe(network, &endpoint, max_kib) {
+            Ok(description) => return Ok(description),
+            Err(e) => debug!("Failed to use oEmbed: {}", e),
+        }
+    }
+
+    fetch_title(network, url, max_kib, rule)
+}
+
+fn fetch_title(
+    network: &str,
+    url: &str,
+    max_kib: usize,
+    rule: &DomainRule,
+) -> Result<String, UrlError> {
+    let url = Url::from(url)
+        .max_kib(max_kib)
+        .preview(PREVIEW_BYTES)
+        .timeout(Duration::from_secs(5))
+        .network(network);
+    let response = url.request_response().context(ErrorKind::Download)?;
+
+    if let Some(description) = describe_file(&response) {
+        return Ok(description);
+    }
+
+    let body = response.text();
+
+    let document = Html::parse_document(&body);
+    if let Some(title) = rule
+        .selector
+        .as_ref()
+        .and_then(|selector| find_selected(&document, selector))
+    {
+        return Ok(title.into());
+    }
+
+    if let Some(endpoint) = oembed::discover(&document, url.as_str()) {
+        match oembed::describe(network, &endpoint, max_kib) {
+            Ok(description) => return Ok(description),
+            Err(e) => debug!("Failed to use discovered oEmbed endpoint: {}", e),
+        }
+    }
+
+    let titles = Title::find_clean_titles(&document, url.as_str());
+
+    // Earlier titles are preferred if they are as useful
+    let title = titles
+        .into_iter()
+        .fold(None, |best: Option<Title>, title| match best {
+            Some(best) if best.usefulness() >= title.usefulness() => Some(best),
+            _ => Some(title),
+        });
+
+    let title = match title {
+        Some(ref title) if title.usefulness() > 0 => title.clone(),
+        title => match Title::find_clean_description(&document, url.as_str()) {
+            Some(description) => description,
+            None if rule.always_show && title.is_some() => title.unwrap(),
+            None if title.is_some() => Err(ErrorKind::UselessTitle)?,
+            None => Err(ErrorKind::MissingTitle)?,
+        },
+    };
+
+    Ok(title.into())
+}
+
+impl<C: FrippyClient> Plugin for UrlTitles<C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        match message.command {
+            Command::PRIVMSG(_, ref msg) => {
+                if URL_RE.is_match(msg) {
+                    ExecutionStatus::RequiresThread
+                } else {
+                    ExecutionStatus::Done
+                }
+            }
+            _ => ExecutionStatus::Done,
+        }
+    }
+
+    fn execute_threaded(
+        &self,
+        client: &Self::Client,
+        message: &Message,
+    ) -> Result<(), FrippyError> {
+        if let Command::PRIVMSG(_, ref content) = message.command {
+            let target = message.response_target().unwrap();
+            let nick = message.source_nickname().unwrap_or("");
+            let urls = self
+                .grep_urls(content)
+                .into_iter()
+                .map(|url| (url, self.rule_for(target, url)))
+                .filter(|(_, rule)| !rule.ignore)
+                .collect::<Vec<_>>();
+            if urls.is_empty() {
+                return Ok(());
+            }
+
+            let notes = urls
+                .iter()
+                .map(|&(url, _)| self.first_poster(target, nick, url))
+                .collect::<Vec<_>>();
+            let prefixes = urls
+                .iter()
+                .map(|(_, rule)| rule.prefix.clone().unwrap_or_else(|| String::from("[URL]")))
+                .collect::<Vec<_>>();
+
+            let mut error = None;
+            let mut parts = Vec::new();
+            let titles = self.titles(&metrics::network(client), &urls);
+            let found = urls
+                .iter()
+                .zip(&titles)
+                .filter_map(|(&(url, _), title)| Some((url, title.as_ref().ok()?.as_str())))
+                .collect::<Vec<_>>();
+            self.remember(target, &found);
+
+            for ((title, note), prefix) in titles.into_iter().zip(notes).zip(prefixes) {
+                let title = match (title, note) {
+                    (Ok(title), Some(note)) => format!("{} {}", title, note),
+                    (Ok(title), None) => title,
+                    (Err(e), _) => {
+                        debug!("Failed to get title: {}", e);
+                        error = error.or(Some(e));
+                        continue;
+                    }
+                };
+
+                parts.push((prefix, title));
+            }
+
+            if parts.is_empty() {
+                let error: UrlError = error.unwrap_or_else(|| ErrorKind::MissingUrl.into());
+                return Err(error.context(FrippyErrorKind::Url).into());
+            }
+
+            // Prefixes are only repeated when they change
+            let mut response = String::new();
+            let mut last_prefix = None;
+            for (prefix, title) in parts {
+                if !response.is_empty() {
+                    response.push_str(" | ");
+                }
+                if last_prefix.as_ref() != Some(&prefix) {
+                    response.push_str(&prefix);
+                    response.push(' ');
+                }
+                response.push_str(&title);
+                last_prefix = Some(prefix);
+            }
+
+            client
+                .send_privmsg(target, response)
+                .context(FrippyErrorKind::Connection)?;
+        }
+
+        Ok(())
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        client
+            .send_privmsg(
+                command.target,
+                "This Plugin does not implement any commands.",
+            )
+            .context(FrippyErrorKind::Connection)?;
+
+        Ok(())
+    }
+
+    fn evaluate(&self, client: &Self::Client, command: PluginCommand) -> Result<String, String> {
+        self.url(
+            &metrics::network(client),
+            &command.target,
+            &command.tokens[0],
+        )
+        .map_err(|e| e.cause().map_or_else(|| e.to_string(), |c| c.to_string()))
+    }
+
+    fn inspect(&self) -> String {
+        let ttl = self.cache_ttl;
+        let cache = self.cache.read();
+        let fresh = cache
+            .values()
+            .filter(|&&(time, _)| time.elapsed() < ttl)
+            .count();
+
+        format!(
+            "{} titles cached, {} of them fresh\n{} of {} first posters remembered",
+            cache.len(),
+            fresh,
+            self.posters.read().len(),
+            MAX_POSTERS
+        )
+    }
+}
+
+impl<C> fmt::Debug for UrlTitles<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UrlTitles {{ ... }}")
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    /// A URL plugin error
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "UrlError"]
+    pub enum ErrorKind {
+        /// A download error
+        #[fail(display = "A download error occured")]
+        Download,
+
+        /// Missing URL error
+        #[fail(display = "No URL was found")]
+        MissingUrl,
+
+        /// Missing title error
+        #[fail(display = "No title was found")]
+        MissingTitle,
+
+        /// Useless title error
+        #[fail(display = "The titles found were not useful enough")]
+        UselessTitle,
+
+        /// Ignored domain error
+        #[fail(display = "The titles of this domain are ignored")]
+        Ignored,
+
+        /// Invalid rule error
+        #[fail(display = "Invalid domain rule")]
+        InvalidRule,
+
+        /// Invalid response error
+        #[fail(display = "The API returned an unexpected response")]
+        InvalidResponse,
+    }
+}
@@ -0,0 +1,193 @@
+//! Describes media with the metadata of oEmbed endpoints
+//! which is often better than the title of the page.
+//!
+//! Endpoints are found through a list of providers or
+//! the `<link>` tags of pages.
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use reqwest::Url as ParsedUrl;
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+use super::error::*;
+use super::single_line;
+use crate::utils::Url;
+use failure::ResultExt;
+use log::debug;
+
+lazy_static! {
+    static ref OEMBED_SELECTOR: Selector =
+        Selector::parse(r#"link[type="application/json+oembed"]"#).unwrap();
+}
+
+/// An oEmbed endpoint and the URL schemes it describes,
+/// schemes may contain `*` as a wildcard.
+#[derive(Clone, Debug)]
+pub struct Provider {
+    pub endpoint: String,
+    pub schemes: Vec<String>,
+}
+
+impl Provider {
+    pub fn new(endpoint: &str, schemes: &[&str]) -> Self {
+        Provider {
+            endpoint: endpoint.to_owned(),
+            schemes: schemes.iter().map(|&s| s.to_owned()).collect(),
+        }
+    }
+}
+
+/// The providers which are known without any configuration.
+pub fn default_providers() -> Vec<Provider> {
+    vec![
+        Provider::new(
+            "https://www.youtube.com/oembed",
+            &[
+                "https://www.youtube.com/watch*",
+                "https://youtube.com/watch*",
+                "https://youtu.be/*",
+            ],
+        ),
+        Provider::new(
+            "https://vimeo.com/api/oembed.json",
+            &["https://vimeo.com/*"],
+        ),
+        Provider::new(
+            "https://soundcloud.com/oembed",
+            &["https://soundcloud.com/*"],
+        ),
+    ]
+}
+
+/// Returns true if `url` matches `scheme`, where `*` matches anything.
+fn matches_scheme(scheme: &str, url: &str) -> bool {
+    let parts = scheme.split('*').collect::<Vec<_>>();
+    if parts.len() == 1 {
+        return scheme == url;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if url.len() < first.len() + last.len() || !url.starts_with(first) || !url.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &url[first.len()..url.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Returns the endpoint describing `url` if a provider knows it.
+pub fn find_endpoint(providers: &[Provider], url: &str) -> Option<String> {
+    let provider = providers
+        .iter()
+        .find(|p| p.schemes.iter().any(|s| matches_scheme(s, url)))?;
+
+    let mut endpoint = ParsedUrl::parse(&provider.endpoint).ok()?;
+    endpoint
+        .query_pairs_mut()
+        .append_pair("url", url)
+        .append_pair("format", "json");
+
+    Some(endpoint.into_string())
+}
+
+/// Finds an endpoint advertised by a page.
+pub fn discover(document: &Html, url: &str) -> Option<String> {
+    let href = document
+        .select(&OEMBED_SELECTOR)
+        .filter(|link| link.value().attr("rel") == Some("alternate"))
+        .filter_map(|link| link.value().attr("href"))
+        .next()?;
+
+    debug!("Found oEmbed endpoint {:?}", href);
+
+    let endpoint = ParsedUrl::parse(url).ok()?.join(href).ok()?;
+
+    Some(endpoint.into_string())
+}
+
+/// Formats seconds like "3:05" or "1:02:03".
+fn format_duration(secs: u64) -> String {
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, minutes, seconds) => format!("{}:{:02}", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+    }
+}
+
+/// Describes media like "Title — author (3:05)".
+pub fn describe(network: &str, endpoint: &str, max_kib: usize) -> Result<String, UrlError> {
+    debug!("Requesting {}", endpoint);
+
+    let body = Url::from(endpoint)
+        .max_kib(max_kib)
+        .timeout(Duration::from_secs(5))
+        .network(network)
+        .request()
+        .context(ErrorKind::Download)?;
+    let json: Value = serde_json::from_str(&body).context(ErrorKind::InvalidResponse)?;
+
+    describe_json(&json)
+}
+
+fn describe_json(json: &Value) -> Result<String, UrlError> {
+    let mut description = json["title"]
+        .as_str()
+        .map(single_line)
+        .filter(|t| !t.is_empty())
+        .ok_or(ErrorKind::InvalidResponse)?;
+
+    if let Some(author) = json["author_name"]
+        .as_str()
+        .map(single_line)
+        .filter(|a| !a.is_empty())
+    {
+        description.push_str(" — ");
+        description.push_str(&author);
+    }
+    // Not part of the specification but some providers send it
+    if let Some(duration) = json["duration"].as_f64().filter(|&d| d > 0.0) {
+        description.push_str(&format!(" ({})", format_duration(duration as u64)));
+    }
+
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn descriptions() {
+        let json = json!({"title": "A song", "author_name": "Band", "duration": 185});
+        assert_eq!(describe_json(&json).unwrap(), "A song — Band (3:05)");
+
+        let json = json!({"title": "  A video  ", "author_name": "", "duration": 3723.5});
+        assert_eq!(describe_json(&json).unwrap(), "A video (1:02:03)");
+
+        let json = json!({"title": " ", "author_name": "Band"});
+        assert_eq!(
+            describe_json(&json).map_err(|e| e.kind()),
+            Err(ErrorKind::InvalidResponse)
+        );
+    }
+
+    #[test]
+    fn multi_line_fields() {
+        let json = json!({
+            "title": "A song\r\nPRIVMSG #chan :hi",
+            "author_name": "Band\nQUIT :bye\r",
+        });
+        assert_eq!(
+            describe_json(&json).unwrap(),
+            "A song||PRIVMSG #chan :hi — Band|QUIT :bye"
+        );
+    }
+}
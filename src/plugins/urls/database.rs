@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+#[cfg(feature = "mysql")]
+use std::sync::Arc;
+
+#[cfg(feature = "mysql")]
+use diesel::mysql::MysqlConnection;
+#[cfg(feature = "mysql")]
+use diesel::prelude::*;
+#[cfg(feature = "mysql")]
+use failure::ResultExt;
+#[cfg(feature = "mysql")]
+use r2d2::Pool;
+#[cfg(feature = "mysql")]
+use r2d2_diesel::ConnectionManager;
+
+#[cfg(feature = "mysql")]
+use crate::utils::escape_like;
+
+use chrono::NaiveDateTime;
+
+use super::error::*;
+
+/// A URL which was posted in a channel.
+///
+/// The title is missing if it could not be found or was not looked up.
+#[cfg_attr(feature = "mysql", derive(Queryable))]
+#[derive(Clone, Debug)]
+pub struct PostedUrl {
+    pub id: i64,
+    pub channel: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub poster: String,
+    pub time: NaiveDateTime,
+}
+
+#[cfg_attr(feature = "mysql", derive(Insertable))]
+#[cfg_attr(feature = "mysql", table_name = "urls")]
+pub struct NewPostedUrl<'a> {
+    pub channel: &'a str,
+    pub url: &'a str,
+    pub title: Option<&'a str>,
+    pub poster: &'a str,
+    pub time: NaiveDateTime,
+}
+
+/// Channels are stored in lowercase, results are sorted newest first.
+pub trait Database: Send + Sync {
+    fn insert_url(&mut self, url: &NewPostedUrl) -> Result<(), UrlsError>;
+    /// Sets the title of the URLs in `channel` which do not have one yet.
+    fn set_title(&mut self, channel: &str, url: &str, title: &str) -> Result<(), UrlsError>;
+    /// Returns URLs whose address or title contains `term`, ignoring case.
+    fn search_urls(
+        &self,
+        channel: &str,
+        term: &str,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError>;
+    /// Returns the newest URLs, only those of `poster` if it is set.
+    fn get_last_urls(
+        &self,
+        channel: &str,
+        poster: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError>;
+    /// Returns URLs posted at or after `start` and before `end`.
+    fn get_urls_between(
+        &self,
+        channel: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError>;
+}
+
+// HashMap
+impl<S: ::std::hash::BuildHasher + Send + Sync> Database for HashMap<String, Vec<PostedUrl>, S> {
+    fn insert_url(&mut self, url: &NewPostedUrl) -> Result<(), UrlsError> {
+        let urls = self.entry(url.channel.to_owned()).or_default();
+
+        urls.push(PostedUrl {
+            id: urls.len() as i64,
+            channel: url.channel.to_owned(),
+            url: url.url.to_owned(),
+            title: url.title.map(str::to_owned),
+            poster: url.poster.to_owned(),
+            time: url.time,
+        });
+
+        Ok(())
+    }
+
+    fn set_title(&mut self, channel: &str, url: &str, title: &str) -> Result<(), UrlsError> {
+        if let Some(urls) = self.get_mut(channel) {
+            for posted in urls
+                .iter_mut()
+                .filter(|u| u.url == url && u.title.is_none())
+            {
+                posted.title = Some(title.to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn search_urls(
+        &self,
+        channel: &str,
+        term: &str,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        let term = term.to_lowercase();
+        let matches = |url: &&PostedUrl| {
+            url.url.to_lowercase().contains(&term)
+                || url
+                    .title
+                    .as_ref()
+                    .map_or(false, |t| t.to_lowercase().contains(&term))
+        };
+
+        Ok(self.get(channel).map_or_else(Vec::new, |urls| {
+            urls.iter()
+                .rev()
+                .filter(matches)
+                .take(limit)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    fn get_last_urls(
+        &self,
+        channel: &str,
+        poster: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        Ok(self.get(channel).map_or_else(Vec::new, |urls| {
+            urls.iter()
+                .rev()
+                .filter(|u| poster.map_or(true, |p| u.poster.eq_ignore_ascii_case(p)))
+                .take(limit)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    fn get_urls_between(
+        &self,
+        channel: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        Ok(self.get(channel).map_or_else(Vec::new, |urls| {
+            urls.iter()
+                .rev()
+                .filter(|u| u.time >= start && u.time < end)
+                .take(limit)
+                .cloned()
+                .collect()
+        }))
+    }
+}
+
+// Diesel automatically defines the urls module as public.
+// We create a schema module to keep it private.
+#[cfg(feature = "mysql")]
+mod schema {
+    table! {
+        urls (id) {
+            id -> Bigint,
+            channel -> Varchar,
+            url -> Text,
+            title -> Nullable<Text>,
+            poster -> Varchar,
+            time -> Timestamp,
+        }
+    }
+}
+
+#[cfg(feature = "mysql")]
+use self::schema::urls;
+
+#[cfg(feature = "mysql")]
+impl Database for Arc<Pool<ConnectionManager<MysqlConnection>>> {
+    fn insert_url(&mut self, url: &NewPostedUrl) -> Result<(), UrlsError> {
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        diesel::insert_into(urls::table)
+            .values(url)
+            .execute(conn)
+            .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn set_title(&mut self, channel: &str, url: &str, title: &str) -> Result<(), UrlsError> {
+        use self::urls::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        diesel::update(
+            urls::table
+                .filter(columns::channel.eq(channel))
+                .filter(columns::url.eq(url))
+                .filter(columns::title.is_null()),
+        )
+        .set(columns::title.eq(title))
+        .execute(conn)
+        .context(ErrorKind::MysqlError)?;
+
+        Ok(())
+    }
+
+    fn search_urls(
+        &self,
+        channel: &str,
+        term: &str,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        use self::urls::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        let pattern = format!("%{}%", escape_like(term));
+
+        Ok(urls::table
+            .filter(columns::channel.eq(channel))
+            .filter(
+                columns::url
+                    .like(&pattern)
+                    .or(columns::title.like(&pattern)),
+            )
+            .order(columns::id.desc())
+            .limit(limit as i64)
+            .load::<PostedUrl>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn get_last_urls(
+        &self,
+        channel: &str,
+        poster: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        use self::urls::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        let mut query = urls::table
+            .filter(columns::channel.eq(channel))
+            .into_boxed();
+        if let Some(poster) = poster {
+            query = query.filter(columns::poster.eq(poster));
+        }
+
+        Ok(query
+            .order(columns::id.desc())
+            .limit(limit as i64)
+            .load::<PostedUrl>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+
+    fn get_urls_between(
+        &self,
+        channel: &str,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        limit: usize,
+    ) -> Result<Vec<PostedUrl>, UrlsError> {
+        use self::urls::columns;
+        let conn = &*self.get().context(ErrorKind::NoConnection)?;
+
+        Ok(urls::table
+            .filter(columns::channel.eq(channel))
+            .filter(columns::time.ge(start))
+            .filter(columns::time.lt(end))
+            .order(columns::id.desc())
+            .limit(limit as i64)
+            .load::<PostedUrl>(conn)
+            .context(ErrorKind::MysqlError)?)
+    }
+}
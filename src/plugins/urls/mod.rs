@@ -0,0 +1,307 @@
+//! Stores and searches the URLs which were posted in a channel.
+//!
+//! `UrlTitles` fills in the titles it finds, see `UrlTitles::history`.
+//! URLs are stored before it starts looking for titles because it runs
+//! in a thread, they keep no title if it is disabled or ignores their domain.
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use antidote::RwLock;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use irc::client::prelude::*;
+use time;
+
+use crate::plugin::*;
+use crate::plugins::url::URL_RE;
+use crate::FrippyClient;
+
+pub mod database;
+use self::database::{Database, NewPostedUrl, PostedUrl};
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::Fail;
+use failure::ResultExt;
+
+use frippy_derive::PluginName;
+
+/// URLs shown by every command.
+const RESULT_COUNT: usize = 5;
+
+fn get_time() -> NaiveDateTime {
+    let tm = time::now().to_timespec();
+    NaiveDateTime::from_timestamp_opt(tm.sec, 0u32).unwrap()
+}
+
+fn is_channel(target: &str) -> bool {
+    target.starts_with('#') || target.starts_with('&')
+}
+
+/// Stores every URL in a message posted in `channel`,
+/// URLs sent in private are ignored.
+pub fn record<T: Database + ?Sized>(
+    db: &RwLock<T>,
+    channel: &str,
+    poster: &str,
+    message: &str,
+) -> Result<(), UrlsError> {
+    if !is_channel(channel) {
+        return Ok(());
+    }
+
+    let channel = channel.to_lowercase();
+    let time = get_time();
+    let mut seen = Vec::new();
+    for captures in URL_RE.captures_iter(message) {
+        let url = captures.get(2).unwrap().as_str();
+        if seen.contains(&url) {
+            continue;
+        }
+        seen.push(url);
+
+        db.write().insert_url(&NewPostedUrl {
+            channel: &channel,
+            url,
+            title: None,
+            poster,
+            time,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Adds the title of a URL which was stored by `record`.
+pub fn set_title<T: Database + ?Sized>(
+    db: &RwLock<T>,
+    channel: &str,
+    url: &str,
+    title: &str,
+) -> Result<(), UrlsError> {
+    if !is_channel(channel) {
+        return Ok(());
+    }
+
+    db.write().set_title(&channel.to_lowercase(), url, title)
+}
+
+#[derive(PluginName)]
+pub struct Urls<T: Database, C> {
+    urls: Arc<RwLock<T>>,
+    phantom: PhantomData<C>,
+}
+
+impl<T: Database, C: FrippyClient> Urls<T, C> {
+    pub fn new(db: T) -> Self {
+        Urls {
+            urls: Arc::new(RwLock::new(db)),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns a handle to the database used by this plugin.
+    pub fn database(&self) -> Arc<RwLock<T>> {
+        Arc::clone(&self.urls)
+    }
+
+    fn list(urls: Vec<PostedUrl>) -> Result<String, UrlsError> {
+        if urls.is_empty() {
+            Err(ErrorKind::NotFound)?;
+        }
+
+        let lines = urls
+            .iter()
+            .map(|u| match u.title {
+                Some(ref title) => format!(
+                    "{} {}: {} - {}",
+                    u.time.format("%Y-%m-%d %H:%M"),
+                    u.poster,
+                    u.url,
+                    title
+                ),
+                None => format!(
+                    "{} {}: {}",
+                    u.time.format("%Y-%m-%d %H:%M"),
+                    u.poster,
+                    u.url
+                ),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(lines.join("\r\n"))
+    }
+
+    fn search(&self, command: &PluginCommand) -> Result<String, UrlsError> {
+        if command.tokens.is_empty() {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let term = command.tokens.join(" ");
+        let channel = command.target.to_lowercase();
+
+        Self::list(
+            self.urls
+                .read()
+                .search_urls(&channel, &term, RESULT_COUNT)?,
+        )
+    }
+
+    fn last(&self, command: &PluginCommand) -> Result<String, UrlsError> {
+        if command.tokens.len() > 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let poster = command.tokens.get(0).map(String::as_str);
+        let channel = command.target.to_lowercase();
+
+        Self::list(
+            self.urls
+                .read()
+                .get_last_urls(&channel, poster, RESULT_COUNT)?,
+        )
+    }
+
+    fn from_date(&self, command: &PluginCommand) -> Result<String, UrlsError> {
+        if command.tokens.len() != 1 {
+            Err(ErrorKind::InvalidCommand)?;
+        }
+
+        let date = NaiveDate::parse_from_str(&command.tokens[0], "%Y-%m-%d")
+            .context(ErrorKind::InvalidDate)?;
+        let start = date.and_hms(0, 0, 0);
+        let channel = command.target.to_lowercase();
+
+        Self::list(self.urls.read().get_urls_between(
+            &channel,
+            start,
+            start + Duration::days(1),
+            RESULT_COUNT,
+        )?)
+    }
+
+    fn invalid_command(&self) -> &str {
+        "Incorrect Command. \
+         Send \"urls help\" for help."
+    }
+
+    fn help(&self) -> &str {
+        "Finds URLs which were posted in this channel, newest first.\r\n\
+         usage: urls <subcommand>\r\n\
+         subcommands: search <term>, last [nick], from <YYYY-MM-DD> (UTC), help"
+    }
+}
+
+impl<T: Database, C: FrippyClient> Plugin for Urls<T, C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        if let Command::PRIVMSG(ref target, ref content) = message.command {
+            let poster = message.source_nickname().unwrap_or("");
+            if let Err(e) = record(&*self.urls, target, poster, content) {
+                return ExecutionStatus::Err(e.context(FrippyErrorKind::Urls).into());
+            }
+        }
+
+        ExecutionStatus::Done
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Urls should not use threading")
+    }
+
+    fn command(
+        &self,
+        client: &Self::Client,
+        mut command: PluginCommand,
+    ) -> Result<(), FrippyError> {
+        if command.tokens.is_empty() {
+            client
+                .send_privmsg(&command.target, self.invalid_command())
+                .context(FrippyErrorKind::Connection)?;
+
+            return Ok(());
+        }
+
+        let sub_command = command.tokens.remove(0);
+        let result = match sub_command.as_ref() {
+            "search" => self.search(&command),
+            "last" => self.last(&command),
+            "from" => self.from_date(&command),
+            "help" => Ok(self.help().to_owned()),
+            _ => Err(ErrorKind::InvalidCommand.into()),
+        };
+
+        match result {
+            Ok(msg) => {
+                client
+                    .send_privmsg(&command.target, &msg)
+                    .context(FrippyErrorKind::Connection)?;
+            }
+            Err(e) => {
+                // This warning only occurs if frippy is built without a database
+                #[allow(unreachable_patterns)]
+                let message = match e.kind() {
+                    ErrorKind::InvalidCommand => self.invalid_command().to_owned(),
+                    ErrorKind::InvalidDate => String::from("Dates look like 2019-05-31"),
+                    ErrorKind::NotFound => String::from("No URLs were found"),
+                    _ => e.to_string(),
+                };
+
+                client
+                    .send_privmsg(&command.target, &message)
+                    .context(FrippyErrorKind::Connection)?;
+
+                match e.kind() {
+                    ErrorKind::InvalidCommand | ErrorKind::InvalidDate | ErrorKind::NotFound => (),
+                    _ => Err(e).context(FrippyErrorKind::Urls)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from(
+            "Evaluation of commands is not implemented for Urls at this time",
+        ))
+    }
+}
+
+impl<T: Database, C: FrippyClient> fmt::Debug for Urls<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Urls {{ ... }}")
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "UrlsError"]
+    pub enum ErrorKind {
+        /// Invalid command error
+        #[fail(display = "Invalid Command")]
+        InvalidCommand,
+
+        /// Invalid date error
+        #[fail(display = "Invalid date")]
+        InvalidDate,
+
+        /// Not found error
+        #[fail(display = "No URLs were found")]
+        NotFound,
+
+        /// MySQL error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "Failed to execute MySQL Query")]
+        MysqlError,
+
+        /// No connection error
+        #[cfg(feature = "mysql")]
+        #[fail(display = "No connection to the database")]
+        NoConnection,
+    }
+}
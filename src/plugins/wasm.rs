@@ -0,0 +1,516 @@
+//! Runs sandboxed plugins compiled to WebAssembly.
+//!
+//! Every call gets a fresh instance of the module which is limited by
+//! an amount of fuel instead of wall clock time and by a maximum size
+//! of its linear memory.
+//!
+//! A module has to export `memory` and `frippy_alloc(len: i32) -> i32`,
+//! which frippy uses to copy strings into the instance.
+//! `frippy_on_message(ptr: i32, len: i32)` receives channel messages and
+//! `frippy_on_command(ptr: i32, len: i32)` receives commands as JSON.
+//! Both are optional.
+//!
+//! The host functions are imported from the `frippy` module.
+//! Strings are passed as pointer and length.
+//! Functions which return data store it in a buffer on the host and return
+//! its length or -1 on failure, the data can then be copied into the
+//! instance with `read_result(ptr, capacity)`.
+//!
+//! * `send_privmsg(target, target_len, message, message_len) -> i32`
+//! * `send_notice(target, target_len, message, message_len) -> i32`
+//! * `config_get(key, key_len) -> i32` reads the option `<plugin>_<key>`
+//! * `storage_get(key, key_len) -> i32`
+//! * `storage_set(key, key_len, value, value_len) -> i32`
+//! * `storage_delete(key, key_len) -> i32`
+//! * `http_get(url, url_len) -> i32`
+//! * `read_result(ptr, capacity) -> i32`
+//! * `log(message, message_len) -> i32`
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use antidote::RwLock;
+use irc::client::prelude::*;
+use serde_json::json;
+use wasmi::core::Trap;
+use wasmi::{Caller, Config as WasmConfig, Engine, Extern, Linker, Module, Store};
+use wasmi::{StoreLimits, StoreLimitsBuilder};
+
+use crate::metrics;
+use crate::plugin::*;
+use crate::utils::Url;
+use crate::FrippyClient;
+
+use self::error::*;
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::ResultExt;
+use log::{debug, error, info};
+
+/// Resources a single call of a plugin is allowed to use.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Fuel is consumed by executing instructions.
+    pub fuel: u64,
+    /// Maximum size of the linear memory in bytes.
+    pub memory: usize,
+    /// Maximum number of HTTP requests.
+    pub requests: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: 100_000_000,
+            memory: 16 * 1024 * 1024,
+            requests: 3,
+        }
+    }
+}
+
+struct HostState<C> {
+    name: String,
+    client: C,
+    storage: Arc<RwLock<HashMap<String, String>>>,
+    result: Vec<u8>,
+    requests: usize,
+    max_requests: usize,
+    limits: StoreLimits,
+}
+
+pub struct Wasm<C> {
+    name: String,
+    engine: Engine,
+    module: Module,
+    limits: Limits,
+    storage: Arc<RwLock<HashMap<String, String>>>,
+    phantom: PhantomData<C>,
+}
+
+impl<C: FrippyClient + 'static> Wasm<C> {
+    /// Compiles the module at `path`, the plugin is named after the file.
+    pub fn load(path: &Path, limits: Limits) -> Result<Self, WasmError> {
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or(ErrorKind::Load)?
+            .to_owned();
+        let bytes = fs::read(path).context(ErrorKind::Load)?;
+
+        let mut config = WasmConfig::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes[..]).context(ErrorKind::Compile)?;
+
+        Ok(Wasm {
+            name,
+            engine,
+            module,
+            limits,
+            storage: Arc::new(RwLock::new(HashMap::new())),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Loads all `.wasm` files in `dir`.
+    /// Modules which fail to load are logged and skipped.
+    pub fn load_dir(dir: &Path, limits: Limits) -> Result<Vec<Self>, WasmError> {
+        let mut plugins = Vec::new();
+
+        for entry in fs::read_dir(dir).context(ErrorKind::Load)? {
+            let path = entry.context(ErrorKind::Load)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("wasm") {
+                continue;
+            }
+
+            match Self::load(&path, limits) {
+                Ok(plugin) => {
+                    info!("Loaded {} from {}", plugin.name, path.display());
+                    plugins.push(plugin);
+                }
+                Err(e) => error!("Failed to load {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(plugins)
+    }
+
+    fn exports(&self, name: &str) -> bool {
+        self.module.get_export(name).is_some()
+    }
+
+    /// Instantiates the module and passes `input` to the exported function `handler`.
+    fn run(&self, client: &C, handler: &str, input: &str) -> Result<(), WasmError> {
+        let state = HostState {
+            name: self.name.clone(),
+            client: client.clone(),
+            storage: Arc::clone(&self.storage),
+            result: Vec::new(),
+            requests: 0,
+            max_requests: self.limits.requests,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.memory)
+                .instances(1)
+                .memories(1)
+                .build(),
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .add_fuel(self.limits.fuel)
+            .map_err(|e| Trap::new(e.to_string()))
+            .context(ErrorKind::Execution)?;
+
+        let instance = linker::<C>(&self.engine)
+            .context(ErrorKind::Compile)?
+            .instantiate(&mut store, &self.module)
+            .context(ErrorKind::Compile)?
+            .start(&mut store)
+            .context(ErrorKind::Execution)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or(ErrorKind::MissingExport)?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "frippy_alloc")
+            .context(ErrorKind::MissingExport)?;
+        let handler = instance
+            .get_typed_func::<(i32, i32), ()>(&store, handler)
+            .context(ErrorKind::MissingExport)?;
+
+        let len = input.len() as i32;
+        let ptr = alloc.call(&mut store, len).context(ErrorKind::Execution)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input.as_bytes())
+            .map_err(|e| Trap::new(e.to_string()))
+            .context(ErrorKind::Execution)?;
+
+        handler
+            .call(&mut store, (ptr, len))
+            .context(ErrorKind::Execution)?;
+
+        debug!(
+            "{} used {} fuel",
+            self.name,
+            store.fuel_consumed().unwrap_or(0)
+        );
+
+        Ok(())
+    }
+}
+
+fn read_string<C>(caller: &Caller<'_, HostState<C>>, ptr: i32, len: i32) -> Result<String, Trap> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("The module does not export its memory"))?;
+
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    let bytes = memory
+        .data(caller)
+        .get(start..end)
+        .ok_or_else(|| Trap::new("Out of bounds string"))?;
+
+    String::from_utf8(bytes.to_vec()).map_err(|e| Trap::new(e.to_string()))
+}
+
+fn set_result<C>(caller: &mut Caller<'_, HostState<C>>, result: Option<Vec<u8>>) -> i32 {
+    match result {
+        Some(result) => {
+            let len = result.len() as i32;
+            caller.data_mut().result = result;
+            len
+        }
+        None => {
+            caller.data_mut().result.clear();
+            -1
+        }
+    }
+}
+
+fn linker<C: FrippyClient + 'static>(
+    engine: &Engine,
+) -> Result<Linker<HostState<C>>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(
+        "frippy",
+        "send_privmsg",
+        |caller: Caller<'_, HostState<C>>,
+         target: i32,
+         target_len: i32,
+         msg: i32,
+         msg_len: i32|
+         -> Result<i32, Trap> {
+            let target = read_string(&caller, target, target_len)?;
+            let message = read_string(&caller, msg, msg_len)?;
+
+            Ok(match caller.data().client.send_privmsg(&target, &message) {
+                Ok(()) => 0,
+                Err(_) => -1,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "send_notice",
+        |caller: Caller<'_, HostState<C>>,
+         target: i32,
+         target_len: i32,
+         msg: i32,
+         msg_len: i32|
+         -> Result<i32, Trap> {
+            let target = read_string(&caller, target, target_len)?;
+            let message = read_string(&caller, msg, msg_len)?;
+
+            Ok(match caller.data().client.send_notice(&target, &message) {
+                Ok(()) => 0,
+                Err(_) => -1,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "config_get",
+        |mut caller: Caller<'_, HostState<C>>, key: i32, key_len: i32| -> Result<i32, Trap> {
+            let key = read_string(&caller, key, key_len)?;
+            let value = {
+                let state = caller.data();
+                let option = format!("{}_{}", state.name.to_lowercase(), key);
+                state
+                    .client
+                    .config()
+                    .options
+                    .as_ref()
+                    .and_then(|options| options.get(&option))
+                    .map(|v| v.as_bytes().to_vec())
+            };
+
+            Ok(set_result(&mut caller, value))
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "storage_get",
+        |mut caller: Caller<'_, HostState<C>>, key: i32, key_len: i32| -> Result<i32, Trap> {
+            let key = read_string(&caller, key, key_len)?;
+            let value = caller
+                .data()
+                .storage
+                .read()
+                .get(&key)
+                .map(|v| v.as_bytes().to_vec());
+
+            Ok(set_result(&mut caller, value))
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "storage_set",
+        |caller: Caller<'_, HostState<C>>,
+         key: i32,
+         key_len: i32,
+         value: i32,
+         value_len: i32|
+         -> Result<i32, Trap> {
+            let key = read_string(&caller, key, key_len)?;
+            let value = read_string(&caller, value, value_len)?;
+            caller.data().storage.write().insert(key, value);
+
+            Ok(0)
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "storage_delete",
+        |caller: Caller<'_, HostState<C>>, key: i32, key_len: i32| -> Result<i32, Trap> {
+            let key = read_string(&caller, key, key_len)?;
+
+            Ok(match caller.data().storage.write().remove(&key) {
+                Some(_) => 0,
+                None => -1,
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "http_get",
+        |mut caller: Caller<'_, HostState<C>>, url: i32, url_len: i32| -> Result<i32, Trap> {
+            let url = read_string(&caller, url, url_len)?;
+
+            if caller.data().requests >= caller.data().max_requests {
+                return Err(Trap::new("Too many HTTP requests"));
+            }
+            caller.data_mut().requests += 1;
+
+            let network = metrics::network(&caller.data().client);
+            let body = Url::from(url)
+                .max_kib(1024)
+                .timeout(Duration::from_secs(5))
+                .network(&network)
+                .request()
+                .ok()
+                .map(String::into_bytes);
+
+            Ok(set_result(&mut caller, body))
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "read_result",
+        |mut caller: Caller<'_, HostState<C>>, ptr: i32, capacity: i32| -> Result<i32, Trap> {
+            let memory = caller
+                .get_export("memory")
+                .and_then(Extern::into_memory)
+                .ok_or_else(|| Trap::new("The module does not export its memory"))?;
+
+            let result = std::mem::take(&mut caller.data_mut().result);
+            let len = result.len().min(capacity as u32 as usize);
+            memory
+                .write(&mut caller, ptr as u32 as usize, &result[..len])
+                .map_err(|e| Trap::new(e.to_string()))?;
+
+            Ok(len as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "frippy",
+        "log",
+        |caller: Caller<'_, HostState<C>>, msg: i32, msg_len: i32| -> Result<i32, Trap> {
+            let message = read_string(&caller, msg, msg_len)?;
+            info!("{}: {}", caller.data().name, message);
+
+            Ok(0)
+        },
+    )?;
+
+    Ok(linker)
+}
+
+impl<C> PluginName for Wasm<C> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<C: FrippyClient + 'static> Plugin for Wasm<C> {
+    type Client = C;
+    fn execute(&self, _: &Self::Client, message: &Message) -> ExecutionStatus {
+        match message.command {
+            Command::PRIVMSG(_, _) if self.exports("frippy_on_message") => {
+                ExecutionStatus::RequiresThread
+            }
+            _ => ExecutionStatus::Done,
+        }
+    }
+
+    fn execute_threaded(
+        &self,
+        client: &Self::Client,
+        message: &Message,
+    ) -> Result<(), FrippyError> {
+        if let Command::PRIVMSG(ref target, ref content) = message.command {
+            let input = json!({
+                "source": message.source_nickname(),
+                "target": message.response_target().unwrap_or(target.as_str()),
+                "content": content,
+            });
+
+            self.run(client, "frippy_on_message", &input.to_string())
+                .context(FrippyErrorKind::Wasm)?;
+        }
+
+        Ok(())
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        if !self.exports("frippy_on_command") {
+            client
+                .send_privmsg(
+                    &command.target,
+                    "This Plugin does not implement any commands.",
+                )
+                .context(FrippyErrorKind::Connection)?;
+
+            return Ok(());
+        }
+
+        let input = json!({
+            "source": command.source,
+            "target": command.target,
+            "tokens": command.tokens,
+        });
+
+        if let Err(e) = self.run(client, "frippy_on_command", &input.to_string()) {
+            client
+                .send_privmsg(&command.target, e.to_string())
+                .context(FrippyErrorKind::Connection)?;
+
+            Err(e).context(FrippyErrorKind::Wasm)?;
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from(
+            "Evaluation of commands is not implemented for wasm plugins at this time",
+        ))
+    }
+
+    fn inspect(&self) -> String {
+        let storage = self.storage.read();
+        let mut keys = storage
+            .iter()
+            .map(|(key, value)| format!("{} ({} bytes)", key, value.len()))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        format!("{} stored keys: {}", keys.len(), keys.join(", "))
+    }
+}
+
+impl<C> fmt::Debug for Wasm<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Wasm {{ name: {:?} }}", self.name)
+    }
+}
+
+pub mod error {
+    use failure::Fail;
+    use frippy_derive::Error;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Fail, Error)]
+    #[error = "WasmError"]
+    pub enum ErrorKind {
+        /// Load error
+        #[fail(display = "Failed to read the module")]
+        Load,
+
+        /// Compile error
+        #[fail(display = "Failed to compile the module")]
+        Compile,
+
+        /// Missing export error
+        #[fail(display = "The module is missing a required export")]
+        MissingExport,
+
+        /// Execution error
+        #[fail(display = "The plugin failed or ran out of fuel or memory")]
+        Execution,
+    }
+}
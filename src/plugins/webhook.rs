@@ -0,0 +1,610 @@
+//! Announces webhook events of GitHub, Gitea and GitLab in IRC channels.
+//!
+//! The listener is started once the bot joins its first channel.
+//! Every request has to be signed with the configured secret which
+//! makes it possible to test the plugin with a locally posted payload:
+//!
+//! ```text
+//! body='{"ref":"refs/heads/master","repository":{"full_name":"Mavulp/frippy"},
+//!        "pusher":{"name":"alice"},"commits":[{"id":"0123456789","message":"Fix"}]}'
+//! sig=$(printf '%s' "$body" | openssl dgst -sha256 -hmac "$secret" | cut -d' ' -f2)
+//! curl -H 'X-GitHub-Event: push' -H "X-Hub-Signature-256: sha256=$sig" \
+//!      -d "$body" http://127.0.0.1:8090/
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::marker::PhantomData;
+use std::thread;
+
+use antidote::RwLock;
+use hmac::{Hmac, Mac};
+use irc::client::prelude::*;
+use serde_json::Value;
+use sha2::Sha256;
+use tiny_http::{Method, Request, Response, Server};
+
+use crate::plugin::*;
+use crate::FrippyClient;
+
+use crate::error::ErrorKind as FrippyErrorKind;
+use crate::error::FrippyError;
+use failure::{format_err, ResultExt};
+use log::{debug, error, info};
+
+use frippy_derive::PluginName;
+
+/// Payloads larger than this are rejected.
+const MAX_PAYLOAD: u64 = 5 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Forge {
+    GitHub,
+    Gitea,
+    GitLab,
+}
+
+/// Everything the listener thread needs to handle requests.
+#[derive(Clone)]
+struct Settings {
+    address: String,
+    secret: String,
+    channels: HashMap<String, Vec<String>>,
+}
+
+#[derive(PluginName)]
+pub struct Webhook<C> {
+    settings: Settings,
+    started: RwLock<bool>,
+    phantom: PhantomData<C>,
+}
+
+impl<C: FrippyClient + 'static> Webhook<C> {
+    /// Creates a plugin which listens on `address` and sends events of
+    /// a repository to the channels `channels` maps its full name to.
+    pub fn new(address: &str, secret: &str, channels: HashMap<String, Vec<String>>) -> Self {
+        Webhook {
+            settings: Settings {
+                address: address.to_owned(),
+                secret: secret.to_owned(),
+                channels: channels
+                    .into_iter()
+                    .map(|(repo, channels)| (repo.to_lowercase(), channels))
+                    .collect(),
+            },
+            started: RwLock::new(false),
+            phantom: PhantomData,
+        }
+    }
+
+    fn start(&self, client: &C) -> Result<(), FrippyError> {
+        if self.settings.secret.is_empty() {
+            Err::<(), _>(format_err!("A webhook secret is required"))
+                .context(FrippyErrorKind::Webhook)?;
+        }
+
+        let server = Server::http(&self.settings.address)
+            .map_err(|e| format_err!("{}", e))
+            .context(FrippyErrorKind::Webhook)?;
+
+        info!("Listening for webhooks on {}", self.settings.address);
+
+        let settings = self.settings.clone();
+        let client = client.clone();
+        thread::Builder::new()
+            .name(String::from("webhook"))
+            .spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&client, &settings, request);
+                }
+            })
+            .context(FrippyErrorKind::ThreadSpawn)?;
+
+        Ok(())
+    }
+}
+
+fn handle<C: FrippyClient>(client: &C, settings: &Settings, mut request: Request) {
+    let status = match receive(&mut request, &settings.secret) {
+        Ok(Some((repo, summary))) => match settings.channels.get(&repo.to_lowercase()) {
+            Some(channels) => {
+                for channel in channels {
+                    if let Err(e) = client.send_privmsg(channel, &summary) {
+                        error!("Failed to announce webhook event: {}", e);
+                    }
+                }
+                200
+            }
+            None => {
+                debug!("No channels are configured for {}", repo);
+                202
+            }
+        },
+        Ok(None) => 202,
+        Err(status) => status,
+    };
+
+    if let Err(e) = request.respond(Response::empty(status)) {
+        error!("Failed to respond to webhook: {}", e);
+    }
+}
+
+/// Verifies the request and returns the repository
+/// and summary of the event if it is one we announce.
+fn receive(request: &mut Request, secret: &str) -> Result<Option<(String, String)>, u16> {
+    if *request.method() != Method::Post {
+        return Err(405);
+    }
+
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_owned())
+    };
+
+    // Gitea also sends the GitHub headers so it has to be checked first
+    let (forge, event, signature) = if let Some(event) = header("X-Gitea-Event") {
+        (Forge::Gitea, event, header("X-Gitea-Signature"))
+    } else if let Some(event) = header("X-GitHub-Event") {
+        let signature =
+            header("X-Hub-Signature-256").and_then(|s| github_signature(&s).map(ToOwned::to_owned));
+        (Forge::GitHub, event, signature)
+    } else if let Some(event) = header("X-Gitlab-Event") {
+        (Forge::GitLab, event, header("X-Gitlab-Token"))
+    } else {
+        return Err(400);
+    };
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_PAYLOAD)
+        .read_to_end(&mut body)
+        .map_err(|_| 400)?;
+
+    let signature = signature.ok_or(401)?;
+    if !verify(forge, secret, &body, &signature) {
+        return Err(401);
+    }
+
+    let payload = serde_json::from_slice::<Value>(&body).map_err(|_| 400)?;
+    Ok(summarize(forge, &event, &payload))
+}
+
+/// GitHub prefixes its signatures with the name of the hash.
+fn github_signature(header: &str) -> Option<&str> {
+    header.strip_prefix("sha256=")
+}
+
+fn verify(forge: Forge, secret: &str, body: &[u8], signature: &str) -> bool {
+    match forge {
+        // GitLab sends the secret itself instead of a signature
+        Forge::GitLab => {
+            signature.len() == secret.len()
+                && signature
+                    .bytes()
+                    .zip(secret.bytes())
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        }
+        Forge::GitHub | Forge::Gitea => {
+            let signature = match hex::decode(signature.trim()) {
+                Ok(signature) => signature,
+                Err(_) => return false,
+            };
+
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC accepts keys of any size");
+            mac.update(body);
+            mac.verify_slice(&signature).is_ok()
+        }
+    }
+}
+
+fn get<'a>(payload: &'a Value, pointer: &str) -> &'a str {
+    payload
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .unwrap_or("")
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
+
+fn short_branch(reference: &str) -> &str {
+    reference
+        .trim_start_matches("refs/heads/")
+        .trim_start_matches("refs/tags/")
+}
+
+/// Formats a single line for the events we announce.
+fn summarize(forge: Forge, event: &str, payload: &Value) -> Option<(String, String)> {
+    let (repo, text) = match (forge, event) {
+        (Forge::GitHub, "push") | (Forge::Gitea, "push") => {
+            let repo = get(payload, "/repository/full_name");
+            let user = match get(payload, "/pusher/name") {
+                "" => get(payload, "/pusher/login"),
+                name => name,
+            };
+            let commits = payload.get("commits")?.as_array()?;
+            let last = commits.last()?;
+
+            let text = format!(
+                "{} pushed {} to {}: {} ({}) {}",
+                user,
+                plural(commits.len(), "commit"),
+                short_branch(get(payload, "/ref")),
+                first_line(get(last, "/message")),
+                get(last, "/id").get(..7).unwrap_or(""),
+                match get(payload, "/compare") {
+                    "" => get(payload, "/compare_url"),
+                    url => url,
+                }
+            );
+            (repo, text)
+        }
+        (Forge::GitLab, "Push Hook") => {
+            let repo = get(payload, "/project/path_with_namespace");
+            let commits = payload.get("commits")?.as_array()?;
+            let checkout = get(payload, "/checkout_sha");
+            let last = commits
+                .iter()
+                .find(|c| get(c, "/id") == checkout)
+                .or_else(|| commits.last())?;
+            let count = payload
+                .get("total_commits_count")
+                .and_then(Value::as_u64)
+                .unwrap_or(commits.len() as u64);
+
+            let text = format!(
+                "{} pushed {} to {}: {} ({}) {}",
+                get(payload, "/user_username"),
+                plural(count as usize, "commit"),
+                short_branch(get(payload, "/ref")),
+                first_line(get(last, "/message")),
+                get(last, "/id").get(..7).unwrap_or(""),
+                get(last, "/url"),
+            );
+            (repo, text)
+        }
+        (Forge::GitHub, "pull_request") | (Forge::Gitea, "pull_request") => {
+            let action = get(payload, "/action");
+            if !["opened", "closed", "reopened"].contains(&action) {
+                return None;
+            }
+
+            let merged = payload
+                .pointer("/pull_request/merged")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let text = format!(
+                "{} {} pull request #{}: {} {}",
+                get(payload, "/sender/login"),
+                if merged { "merged" } else { action },
+                payload.get("number")?,
+                get(payload, "/pull_request/title"),
+                get(payload, "/pull_request/html_url"),
+            );
+            (get(payload, "/repository/full_name"), text)
+        }
+        (Forge::GitLab, "Merge Request Hook") => {
+            let action = match get(payload, "/object_attributes/action") {
+                "open" => "opened",
+                "close" => "closed",
+                "reopen" => "reopened",
+                "merge" => "merged",
+                _ => return None,
+            };
+
+            let text = format!(
+                "{} {} merge request !{}: {} {}",
+                get(payload, "/user/username"),
+                action,
+                payload.pointer("/object_attributes/iid")?,
+                get(payload, "/object_attributes/title"),
+                get(payload, "/object_attributes/url"),
+            );
+            (get(payload, "/project/path_with_namespace"), text)
+        }
+        (Forge::GitHub, "issues") | (Forge::Gitea, "issues") => {
+            let action = get(payload, "/action");
+            if !["opened", "closed", "reopened"].contains(&action) {
+                return None;
+            }
+
+            let text = format!(
+                "{} {} issue #{}: {} {}",
+                get(payload, "/sender/login"),
+                action,
+                payload.pointer("/issue/number")?,
+                get(payload, "/issue/title"),
+                get(payload, "/issue/html_url"),
+            );
+            (get(payload, "/repository/full_name"), text)
+        }
+        (Forge::GitLab, "Issue Hook") => {
+            let action = match get(payload, "/object_attributes/action") {
+                "open" => "opened",
+                "close" => "closed",
+                "reopen" => "reopened",
+                _ => return None,
+            };
+
+            let text = format!(
+                "{} {} issue #{}: {} {}",
+                get(payload, "/user/username"),
+                action,
+                payload.pointer("/object_attributes/iid")?,
+                get(payload, "/object_attributes/title"),
+                get(payload, "/object_attributes/url"),
+            );
+            (get(payload, "/project/path_with_namespace"), text)
+        }
+        (Forge::GitHub, "workflow_run") | (Forge::Gitea, "workflow_run") => {
+            if get(payload, "/action") != "completed" {
+                return None;
+            }
+
+            let text = format!(
+                "{} {} on {} {}",
+                get(payload, "/workflow_run/name"),
+                get(payload, "/workflow_run/conclusion"),
+                get(payload, "/workflow_run/head_branch"),
+                get(payload, "/workflow_run/html_url"),
+            );
+            (get(payload, "/repository/full_name"), text)
+        }
+        (Forge::GitLab, "Pipeline Hook") => {
+            let status = get(payload, "/object_attributes/status");
+            if !["success", "failed", "canceled"].contains(&status) {
+                return None;
+            }
+
+            let id = payload.pointer("/object_attributes/id")?;
+            let text = format!(
+                "Pipeline {} {} on {} {}/-/pipelines/{}",
+                id,
+                status,
+                get(payload, "/object_attributes/ref"),
+                get(payload, "/project/web_url"),
+                id,
+            );
+            (get(payload, "/project/path_with_namespace"), text)
+        }
+        _ => return None,
+    };
+
+    if repo.is_empty() {
+        return None;
+    }
+
+    let name = repo.rsplit('/').next().unwrap_or(repo);
+    Some((repo.to_owned(), format!("[{}] {}", name, text.trim_end())))
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+impl<C: FrippyClient + 'static> Plugin for Webhook<C> {
+    type Client = C;
+    fn execute(&self, client: &Self::Client, message: &Message) -> ExecutionStatus {
+        if let Command::JOIN(_, _, _) = message.command {
+            let mut started = self.started.write();
+
+            if !*started {
+                // Only try once so a failure is not repeated on every join
+                *started = true;
+
+                if let Err(e) = self.start(client) {
+                    return ExecutionStatus::Err(e);
+                }
+            }
+        }
+
+        ExecutionStatus::Done
+    }
+
+    fn execute_threaded(&self, _: &Self::Client, _: &Message) -> Result<(), FrippyError> {
+        panic!("Webhook should not use threading")
+    }
+
+    fn command(&self, client: &Self::Client, command: PluginCommand) -> Result<(), FrippyError> {
+        client
+            .send_privmsg(
+                command.target,
+                "This Plugin does not implement any commands.",
+            )
+            .context(FrippyErrorKind::Connection)?;
+
+        Ok(())
+    }
+
+    fn evaluate(&self, _: &Self::Client, _: PluginCommand) -> Result<String, String> {
+        Err(String::from("This Plugin does not implement any commands."))
+    }
+}
+
+impl<C> fmt::Debug for Webhook<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Webhook {{ address: {:?}, channels: {:?} }}",
+            self.settings.address, self.settings.channels
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:expr) => {
+            serde_json::from_str::<Value>(include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/webhook/",
+                $name,
+                ".json"
+            )))
+            .unwrap()
+        };
+    }
+
+    fn summary(forge: Forge, event: &str, payload: &Value) -> (String, String) {
+        summarize(forge, event, payload).expect("event should be announced")
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn github_push() {
+        let payload = fixture!("github_push");
+        assert_eq!(
+            summary(Forge::GitHub, "push", &payload),
+            (
+                String::from("Mavulp/frippy"),
+                String::from(
+                    "[frippy] alice pushed 2 commits to master: Fix crash in parser (9f2c4e1) \
+                     https://github.com/Mavulp/frippy/compare/1111111111...9f2c4e1a7b"
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn github_pull_request() {
+        let payload = fixture!("github_pull_request");
+        assert_eq!(
+            summary(Forge::GitHub, "pull_request", &payload).1,
+            "[frippy] alice merged pull request #42: Label metrics by network \
+             https://github.com/Mavulp/frippy/pull/42"
+        );
+    }
+
+    #[test]
+    fn github_workflow_run() {
+        let payload = fixture!("github_workflow_run");
+        assert_eq!(
+            summary(Forge::GitHub, "workflow_run", &payload).1,
+            "[frippy] CI failure on master https://github.com/Mavulp/frippy/actions/runs/30433642"
+        );
+    }
+
+    #[test]
+    fn gitea_push() {
+        let payload = fixture!("gitea_push");
+        assert_eq!(
+            summary(Forge::Gitea, "push", &payload).1,
+            "[frippy] carol pushed 1 commit to develop: Update dependencies (c0ffee1) \
+             https://codeberg.org/Mavulp/frippy/compare/2222222222...c0ffee1234"
+        );
+    }
+
+    #[test]
+    fn gitea_pull_request() {
+        let payload = fixture!("gitea_pull_request");
+        assert_eq!(
+            summary(Forge::Gitea, "pull_request", &payload).1,
+            "[frippy] carol opened pull request #7: Add feeds plugin \
+             https://codeberg.org/Mavulp/frippy/pulls/7"
+        );
+    }
+
+    #[test]
+    fn gitea_workflow_run() {
+        let payload = fixture!("gitea_workflow_run");
+        assert_eq!(
+            summary(Forge::Gitea, "workflow_run", &payload).1,
+            "[frippy] test success on develop https://codeberg.org/Mavulp/frippy/actions/runs/815"
+        );
+    }
+
+    #[test]
+    fn gitlab_push() {
+        let payload = fixture!("gitlab_push");
+        assert_eq!(
+            summary(Forge::GitLab, "Push Hook", &payload),
+            (
+                String::from("group/subgroup/frippy"),
+                String::from(
+                    "[frippy] dave pushed 4 commits to main: Release 0.6 (da15608) \
+                     https://gitlab.com/group/subgroup/frippy/-/commit/\
+                     da1560886d4f094c3e6c9ef40349f7d38b5d27d7"
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn gitlab_merge_request() {
+        let payload = fixture!("gitlab_merge_request");
+        assert_eq!(
+            summary(Forge::GitLab, "Merge Request Hook", &payload).1,
+            "[frippy] dave merged merge request !12: Cache titles \
+             https://gitlab.com/group/subgroup/frippy/-/merge_requests/12"
+        );
+    }
+
+    #[test]
+    fn gitlab_pipeline() {
+        let payload = fixture!("gitlab_pipeline");
+        assert_eq!(
+            summary(Forge::GitLab, "Pipeline Hook", &payload).1,
+            "[frippy] Pipeline 31 failed on main \
+             https://gitlab.com/group/subgroup/frippy/-/pipelines/31"
+        );
+    }
+
+    #[test]
+    fn ignored_events() {
+        let mut payload = fixture!("github_pull_request");
+        payload["action"] = Value::from("synchronize");
+        assert_eq!(summarize(Forge::GitHub, "pull_request", &payload), None);
+
+        let payload = fixture!("github_push");
+        assert_eq!(summarize(Forge::GitHub, "star", &payload), None);
+    }
+
+    #[test]
+    fn verify_hmac() {
+        let body = br#"{"ref":"refs/heads/master"}"#;
+        let signature = sign("secret", body);
+
+        assert!(verify(Forge::GitHub, "secret", body, &signature));
+        assert!(verify(Forge::Gitea, "secret", body, &signature));
+        assert!(!verify(Forge::GitHub, "other", body, &signature));
+        assert!(!verify(Forge::GitHub, "secret", b"{}", &signature));
+        assert!(!verify(Forge::Gitea, "secret", body, "not hex"));
+    }
+
+    #[test]
+    fn verify_github_prefix() {
+        let body = b"{}";
+        let header = format!("sha256={}", sign("secret", body));
+
+        let signature = github_signature(&header).unwrap();
+        assert!(verify(Forge::GitHub, "secret", body, signature));
+        assert_eq!(github_signature(&sign("secret", body)), None);
+        assert_eq!(github_signature("sha1=0123"), None);
+    }
+
+    #[test]
+    fn verify_gitlab_token() {
+        assert!(verify(Forge::GitLab, "secret", b"{}", "secret"));
+        assert!(!verify(Forge::GitLab, "secret", b"{}", "secreT"));
+        assert!(!verify(Forge::GitLab, "secret", b"{}", "secret2"));
+        assert!(!verify(Forge::GitLab, "secret", b"{}", ""));
+    }
+}
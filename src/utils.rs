@@ -1,18 +1,526 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::thread;
 use std::time::Duration;
 
-use reqwest::header::{HeaderValue, ACCEPT_LANGUAGE, CONNECTION};
-use reqwest::{Client, ClientBuilder};
+use antidote::{Mutex, RwLock};
+
+use encoding_rs::{Encoding, UTF_8};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION,
+    USER_AGENT,
+};
+use reqwest::{Client, ClientBuilder, Proxy, RedirectPolicy, StatusCode, Url as ParsedUrl};
 
 use self::error::{DownloadError, ErrorKind};
+use crate::metrics;
 use failure::ResultExt;
+use log::debug;
+
+lazy_static! {
+    static ref META_CHARSET_RE: Regex =
+        Regex::new(r#"(?i-u)<meta[^>]+charset\s*=\s*["']?([\w-]+)"#).unwrap();
+    static ref ALLOWED_HOSTS: RwLock<Vec<AllowedHost>> = RwLock::new(Vec::new());
+    static ref HTTP: Mutex<HttpClients> = Mutex::new(HttpClients {
+        config: HttpConfig::default(),
+        clients: HashMap::new(),
+    });
+}
+
+/// The longest wait between retries of a request.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Settings of the HTTP client which is shared by all requests.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    /// An http://, https:// or socks5:// proxy used for every request
+    pub proxy: Option<String>,
+    pub max_redirects: usize,
+    /// Failed requests are retried this many times
+    pub retries: u32,
+    /// The time before the first retry which doubles with every retry
+    pub backoff: Duration,
+    pub accept_language: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            user_agent: format!("frippy/{}", env!("CARGO_PKG_VERSION")),
+            proxy: None,
+            max_redirects: 10,
+            retries: 2,
+            backoff: Duration::from_millis(500),
+            accept_language: String::from("en-US,en;q=0.5"),
+        }
+    }
+}
+
+/// Clients are reused so connections can be kept alive,
+/// one is needed per timeout.
+struct HttpClients {
+    config: HttpConfig,
+    clients: HashMap<Option<Duration>, Client>,
+}
+
+fn build_client(config: &HttpConfig, timeout: Option<Duration>) -> Result<Client, DownloadError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        USER_AGENT,
+        HeaderValue::from_str(&config.user_agent).context(ErrorKind::InvalidConfig)?,
+    );
+    headers.insert(
+        ACCEPT_LANGUAGE,
+        HeaderValue::from_str(&config.accept_language).context(ErrorKind::InvalidConfig)?,
+    );
+
+    // Redirects are followed by hand so every host can be checked
+    let mut builder = ClientBuilder::new()
+        .default_headers(headers)
+        .redirect(RedirectPolicy::none());
+    if let Some(ref proxy) = config.proxy {
+        builder = builder.proxy(Proxy::all(proxy.as_str()).context(ErrorKind::InvalidConfig)?);
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    Ok(builder.build().context(ErrorKind::Connection)?)
+}
+
+/// Replaces the settings used by every following request.
+///
+/// The error indicates an invalid user agent, language or proxy.
+pub fn configure_http(config: HttpConfig) -> Result<(), DownloadError> {
+    build_client(&config, None)?;
+
+    let mut http = HTTP.lock();
+    http.config = config;
+    http.clients.clear();
+
+    Ok(())
+}
+
+/// Returns the shared client for `timeout` and the settings it was built with.
+fn http_client(timeout: Option<Duration>) -> Result<(Client, HttpConfig), DownloadError> {
+    let mut http = HTTP.lock();
+    if let Some(client) = http.clients.get(&timeout) {
+        return Ok((client.clone(), http.config.clone()));
+    }
+
+    let client = build_client(&http.config, timeout)?;
+    http.clients.insert(timeout, client.clone());
+
+    Ok((client, http.config.clone()))
+}
+
+/// Sends a GET request and retries it if the connection fails
+/// or the server reports a temporary error.
+///
+/// Plain HTTP requests are sent to `address` if it is set so the host
+/// cannot resolve to another address than the one which was checked.
+/// HTTPS needs the host name to verify the certificate of the server.
+fn send(
+    client: &Client,
+    config: &HttpConfig,
+    url: &ParsedUrl,
+    address: Option<IpAddr>,
+) -> Result<reqwest::Response, DownloadError> {
+    let (target, host) = match address {
+        Some(address) if url.scheme() == "http" => {
+            let host = url.host_str().ok_or(ErrorKind::InvalidUrl)?;
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_owned(),
+            };
+
+            let mut target = url.clone();
+            target
+                .set_ip_host(address)
+                .map_err(|_| ErrorKind::InvalidUrl)?;
+
+            (
+                target,
+                Some(HeaderValue::from_str(&host).context(ErrorKind::InvalidUrl)?),
+            )
+        }
+        _ => (url.clone(), None),
+    };
+
+    let mut attempt = 0;
+    loop {
+        let mut request = client.get(target.clone());
+        if let Some(ref host) = host {
+            request = request.header(HOST, host.clone());
+        }
+
+        let result = request.send();
+        let temporary = match result {
+            Ok(ref response) => {
+                response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(_) => true,
+        };
+
+        if !temporary || attempt >= config.retries {
+            return Ok(result.context(ErrorKind::Connection)?);
+        }
+
+        debug!("Retrying {} after attempt {} failed", url, attempt + 1);
+        let wait = config
+            .backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(MAX_BACKOFF, |wait| wait.min(MAX_BACKOFF));
+        thread::sleep(wait);
+        attempt += 1;
+    }
+}
+
+/// A host name or network which may be requested even though
+/// it is not reachable from the internet.
+#[derive(Clone, Debug)]
+enum AllowedHost {
+    Name(String),
+    Network(IpAddr, u8),
+}
+
+impl AllowedHost {
+    /// Parses a host name, an address or a network like "10.0.0.0/8".
+    fn parse(host: &str) -> Self {
+        let mut parts = host.splitn(2, '/');
+        let address = parts
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == '[' || c == ']');
+
+        if let Ok(address) = address.parse::<IpAddr>() {
+            let bits = if address.is_ipv4() { 32 } else { 128 };
+            match parts.next().map(str::parse) {
+                None => return AllowedHost::Network(address, bits),
+                Some(Ok(prefix)) if prefix <= bits => return AllowedHost::Network(address, prefix),
+                _ => (),
+            }
+        }
+
+        AllowedHost::Name(host.to_lowercase())
+    }
+
+    fn matches_name(&self, host: &str) -> bool {
+        match *self {
+            AllowedHost::Name(ref name) => name.eq_ignore_ascii_case(host),
+            AllowedHost::Network(..) => false,
+        }
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        let (network, address, prefix, bits) = match (self, address) {
+            (&AllowedHost::Network(IpAddr::V4(network), prefix), IpAddr::V4(address)) => (
+                u128::from(u32::from(network)),
+                u128::from(u32::from(address)),
+                prefix,
+                32,
+            ),
+            (&AllowedHost::Network(IpAddr::V6(network), prefix), IpAddr::V6(address)) => {
+                (u128::from(network), u128::from(address), prefix, 128)
+            }
+            _ => return false,
+        };
+
+        prefix == 0 || network >> (bits - prefix) == address >> (bits - prefix)
+    }
+}
+
+/// Allows requests to hosts which are blocked by default,
+/// like "localhost", "192.168.1.10" or "10.0.0.0/8".
+pub fn allow_hosts(hosts: &[&str]) {
+    ALLOWED_HOSTS.write().extend(
+        hosts
+            .iter()
+            .map(|h| h.trim())
+            .filter(|h| !h.is_empty())
+            .map(AllowedHost::parse),
+    );
+}
+
+/// Treats IPv4 addresses mapped into IPv6 like the IPv4 address.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => IpAddr::V4(v6.to_ipv4().unwrap()),
+            _ => address,
+        },
+        IpAddr::V4(_) => address,
+    }
+}
+
+/// Returns the IPv4 address embedded into IPv4-compatible, NAT64 and 6to4
+/// addresses which are translated to it by a gateway.
+fn embedded_ipv4(segments: [u16; 8]) -> Option<Ipv4Addr> {
+    let (high, low) = match segments {
+        // Deprecated IPv4-compatible addresses, ::/96
+        [0, 0, 0, 0, 0, 0, high, low] => (high, low),
+        // Well-known and local-use NAT64 prefixes, 64:ff9b::/96 and 64:ff9b:1::/48
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] | [0x64, 0xff9b, 1, _, _, _, high, low] => {
+            (high, low)
+        }
+        // 6to4, 2002::/16
+        [0x2002, high, low, _, _, _, _, _] => (high, low),
+        _ => return None,
+    };
+
+    Some(Ipv4Addr::from(u32::from(high) << 16 | u32::from(low)))
+}
+
+/// Returns false for loopback, private, link-local and other addresses
+/// which are not reachable from the internet.
+/// Cloud metadata services live in the link-local
+/// and shared address ranges.
+fn is_public(address: IpAddr) -> bool {
+    match canonical(address) {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || octets[0] == 0
+                || octets[0] >= 240
+                // Shared address space, 100.64.0.0/10
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = embedded_ipv4(v6.segments()) {
+                return is_public(IpAddr::V4(v4));
+            }
+
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // Unique local, fc00::/7
+                || first & 0xfe00 == 0xfc00
+                // Link-local and the deprecated site-local, fe80::/10 and fec0::/10
+                || first & 0xffc0 == 0xfe80
+                || first & 0xffc0 == 0xfec0)
+        }
+    }
+}
+
+/// Returns true for names which resolve to the local host or network,
+/// `localhost`, `*.localhost` and names without a dot.
+fn is_local_name(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_lowercase();
+    host == "localhost" || host.ends_with(".localhost") || !host.contains('.')
+}
+
+/// Refuses URLs which are not http(s) or whose host resolves to
+/// an address that is not public and not allowed by `allow_hosts()`.
+///
+/// Returns the checked address requests should connect to if the host
+/// is a name which was resolved here.
+/// Names are left to the proxy if `proxied` is set, only addresses
+/// and local names are checked then.
+fn check_host(url: &ParsedUrl, proxied: bool) -> Result<Option<IpAddr>, DownloadError> {
+    match url.scheme() {
+        "http" | "https" => (),
+        _ => Err(ErrorKind::Forbidden)?,
+    }
+
+    let host = url
+        .host_str()
+        .ok_or(ErrorKind::InvalidUrl)?
+        .trim_matches(|c| c == '[' || c == ']');
+    let allowed = ALLOWED_HOSTS.read();
+    let check = |address: IpAddr| -> Result<(), DownloadError> {
+        let address = canonical(address);
+        if !is_public(address) && !allowed.iter().any(|a| a.contains(address)) {
+            Err(ErrorKind::Forbidden)?;
+        }
+
+        Ok(())
+    };
+
+    if let Ok(address) = host.parse::<IpAddr>() {
+        check(address)?;
+        return Ok(None);
+    }
+    if allowed.iter().any(|a| a.matches_name(host)) {
+        return Ok(None);
+    }
+    if proxied {
+        if is_local_name(host) {
+            Err(ErrorKind::Forbidden)?;
+        }
+        return Ok(None);
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses = (host, port)
+        .to_socket_addrs()
+        .context(ErrorKind::Connection)?
+        .map(|a| a.ip())
+        .collect::<Vec<_>>();
+
+    for &address in &addresses {
+        check(address)?;
+    }
+
+    Ok(Some(*addresses.first().ok_or(ErrorKind::Connection)?))
+}
+
+/// Escapes the wildcards of a MySQL `LIKE` pattern so `text` only matches itself.
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Lowercases nicks and channels using the rfc1459 casemapping
+/// where `[]\~` are the uppercase versions of `{}|^`.
+pub fn irc_lowercase(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '[' => '{',
+            ']' => '}',
+            '\\' => '|',
+            '~' => '^',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Returns true if a prefix like "nick!user@host" matches `mask`,
+/// where `*` matches any number of characters and `?` matches one.
+pub fn matches_mask(mask: &str, prefix: &str) -> bool {
+    let mask = irc_lowercase(mask).chars().collect::<Vec<_>>();
+    let prefix = irc_lowercase(prefix).chars().collect::<Vec<_>>();
+
+    // Position after the last `*` and where it started matching in `prefix`
+    let mut star = None;
+    let (mut m, mut p) = (0, 0);
+    while p < prefix.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m + 1, p));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == prefix[p] => {
+                m += 1;
+                p += 1;
+            }
+            _ => match star {
+                Some((star_m, star_p)) => {
+                    star = Some((star_m, star_p + 1));
+                    m = star_m;
+                    p = star_p + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+fn mime_type(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::trim)
+}
+
+fn is_markup(mime_type: Option<&str>) -> bool {
+    match mime_type {
+        Some(mime) => {
+            mime.starts_with("text/html") || mime.ends_with("/xml") || mime.ends_with("+xml")
+        }
+        None => true,
+    }
+}
+
+/// Returns true if a body without a `Content-Type` seems to be HTML or XML.
+fn looks_like_markup(bytes: &[u8]) -> bool {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    bytes
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .map_or(true, |&b| b == b'<')
+}
+
+/// The headers and the downloaded part of a body.
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub headers: HeaderMap,
+    pub bytes: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the `Content-Type` without its parameters.
+    pub fn mime_type(&self) -> Option<&str> {
+        mime_type(&self.headers)
+    }
+
+    /// Returns the `Content-Length` of the complete body.
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+    }
+
+    /// Returns true if the body is HTML or XML or has no known type.
+    pub fn is_markup(&self) -> bool {
+        is_markup(self.mime_type())
+    }
+
+    /// Finds the encoding in the `Content-Type` header
+    /// or in a `<meta>` tag near the start of the body.
+    fn encoding(&self) -> &'static Encoding {
+        let from_header = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| {
+                v.split(';')
+                    .map(str::trim)
+                    .find(|p| p.to_lowercase().starts_with("charset="))
+            })
+            .and_then(|p| Encoding::for_label(p["charset=".len()..].trim_matches('"').as_bytes()));
+
+        from_header
+            .or_else(|| {
+                let start = &self.bytes[..self.bytes.len().min(1024)];
+                META_CHARSET_RE
+                    .captures(start)
+                    .and_then(|c| Encoding::for_label(&c[1]))
+            })
+            .unwrap_or(UTF_8)
+    }
+
+    /// Decodes the body, any invalid bytes are converted to
+    /// a replacement character.
+    pub fn text(&self) -> String {
+        let (text, _, _) = self.encoding().decode(&self.bytes);
+        text.into_owned()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Url<'a> {
     url: Cow<'a, str>,
     max_kib: Option<usize>,
     timeout: Option<Duration>,
+    preview_bytes: Option<usize>,
+    network: Option<String>,
 }
 
 impl<'a> From<String> for Url<'a> {
@@ -21,6 +529,8 @@ impl<'a> From<String> for Url<'a> {
             url: Cow::from(url),
             max_kib: None,
             timeout: None,
+            preview_bytes: None,
+            network: None,
         }
     }
 }
@@ -31,6 +541,8 @@ impl<'a> From<&'a str> for Url<'a> {
             url: Cow::from(url),
             max_kib: None,
             timeout: None,
+            preview_bytes: None,
+            network: None,
         }
     }
 }
@@ -46,24 +558,67 @@ impl<'a> Url<'a> {
         self
     }
 
-    /// Downloads the file and converts it to a String.
+    /// Labels the download metrics with the network the request was made for.
+    pub fn network(mut self, network: &str) -> Self {
+        self.network = Some(network.to_owned());
+        self
+    }
+
+    /// Only reads the first `bytes` of responses which are not markup,
+    /// which is enough to tell what kind of file they are.
+    /// Responses without a type are only read completely if they start like markup.
+    pub fn preview(mut self, bytes: usize) -> Self {
+        self.preview_bytes = Some(bytes);
+        self
+    }
+
+    /// Downloads the file and converts it to a String using
+    /// the charset of the response.
     /// Any invalid bytes are converted to a replacement character.
     ///
     /// The error indicated either a failed download or
     /// that the limit set by max_kib() was reached.
     pub fn request(&self) -> Result<String, DownloadError> {
-        let client = if let Some(timeout) = self.timeout {
-            ClientBuilder::new().timeout(timeout).build().unwrap()
-        } else {
-            Client::new()
+        self.request_response().map(|r| r.text())
+    }
+
+    /// Downloads the file and keeps the headers of the response.
+    pub fn request_response(&self) -> Result<Response, DownloadError> {
+        let network = self.network.as_ref().map_or("unknown", String::as_str);
+        let _timer = metrics::URL_FETCH
+            .with_label_values(&[network])
+            .start_timer();
+
+        let (client, config) = http_client(self.timeout)?;
+
+        let mut url = ParsedUrl::parse(&self.url).context(ErrorKind::InvalidUrl)?;
+        let mut redirects = 0;
+        let mut response = loop {
+            let address = check_host(&url, config.proxy.is_some())?;
+
+            let response = send(&client, &config, &url, address)?;
+
+            if !response.status().is_redirection() {
+                break response;
+            }
+
+            let location = match response.headers().get(LOCATION) {
+                Some(location) => location.to_str().context(ErrorKind::InvalidUrl)?,
+                None => break response,
+            };
+            url = url.join(location).context(ErrorKind::InvalidUrl)?;
+
+            redirects += 1;
+            if redirects > config.max_redirects {
+                Err(ErrorKind::TooManyRedirects)?;
+            }
         };
 
-        let mut response = client
-            .get(self.url.as_ref())
-            .header(CONNECTION, HeaderValue::from_static("close"))
-            .header(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.5"))
-            .send()
-            .context(ErrorKind::Connection)?;
+        // Bodies without a type are previewed until they look like markup
+        let undeclared = mime_type(response.headers()).is_none();
+        let mut preview_bytes = self
+            .preview_bytes
+            .filter(|_| undeclared || !is_markup(mime_type(response.headers())));
 
         // 100 kibibyte buffer
         let mut buf = [0; 100 * 1024];
@@ -82,6 +637,17 @@ impl<'a> Url<'a> {
             bytes.extend_from_slice(&buf[..len]);
             written += len;
 
+            if let Some(preview) = preview_bytes {
+                if written >= preview {
+                    if undeclared && looks_like_markup(&bytes) {
+                        preview_bytes = None;
+                    } else {
+                        bytes.truncate(preview);
+                        break;
+                    }
+                }
+            }
+
             // Check if the file is too large to download
             if let Some(max_kib) = self.max_kib {
                 if written > max_kib * 1024 {
@@ -90,7 +656,10 @@ impl<'a> Url<'a> {
             }
         }
 
-        Ok(String::from_utf8_lossy(&bytes).into_owned())
+        Ok(Response {
+            headers: response.headers().clone(),
+            bytes,
+        })
     }
 
     pub fn as_str(&self) -> &str {
@@ -116,5 +685,185 @@ pub mod error {
         /// Reached download limit error
         #[fail(display = "Reached download limit")]
         DownloadLimit,
+
+        /// Invalid URL error
+        #[fail(display = "The URL is invalid")]
+        InvalidUrl,
+
+        /// Forbidden host error
+        #[fail(display = "Requests to this host are not allowed")]
+        Forbidden,
+
+        /// Redirect limit error
+        #[fail(display = "Too many redirects")]
+        TooManyRedirects,
+
+        /// Invalid settings error
+        #[fail(display = "Invalid HTTP settings")]
+        InvalidConfig,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(address: &str) -> bool {
+        is_public(address.parse().expect("address should be valid"))
+    }
+
+    fn network(host: &str, address: &str) -> bool {
+        AllowedHost::parse(host).contains(address.parse().expect("address should be valid"))
+    }
+
+    #[test]
+    fn blocked_ipv4_ranges() {
+        for address in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.10",
+            "169.254.169.254",
+            "255.255.255.255",
+            "0.0.0.0",
+            "0.1.2.3",
+            "224.0.0.1",
+            "240.0.0.1",
+            "100.64.0.1",
+            "100.127.255.255",
+        ] {
+            assert!(!public(address), "{}", address);
+        }
+
+        for address in &["1.1.1.1", "172.32.0.1", "100.63.255.255", "100.128.0.1"] {
+            assert!(public(address), "{}", address);
+        }
+    }
+
+    #[test]
+    fn blocked_ipv6_ranges() {
+        for address in &[
+            "::1", "::", "ff02::1", "fc00::1", "fd12::1", "fe80::1", "fec0::1",
+        ] {
+            assert!(!public(address), "{}", address);
+        }
+
+        assert!(public("2001:4860:4860::8888"));
+    }
+
+    #[test]
+    fn embedded_addresses() {
+        // Mapped, IPv4-compatible, NAT64 and 6to4 addresses are checked like IPv4
+        for address in &[
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::169.254.169.254",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b:1::a9fe:a9fe",
+            "2002:c0a8:0101::1",
+        ] {
+            assert!(!public(address), "{}", address);
+        }
+
+        for address in &["::ffff:1.1.1.1", "64:ff9b::1.1.1.1", "2002:0101:0101::1"] {
+            assert!(public(address), "{}", address);
+        }
+    }
+
+    #[test]
+    fn embedded_ipv4_addresses() {
+        let embedded = |address: &str| {
+            embedded_ipv4(
+                address
+                    .parse::<std::net::Ipv6Addr>()
+                    .expect("address should be valid")
+                    .segments(),
+            )
+        };
+
+        assert_eq!(embedded("::10.0.0.1"), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(
+            embedded("64:ff9b::c0a8:1"),
+            Some(Ipv4Addr::new(192, 168, 0, 1))
+        );
+        assert_eq!(
+            embedded("64:ff9b:1:2:3:4:7f00:1"),
+            Some(Ipv4Addr::new(127, 0, 0, 1))
+        );
+        assert_eq!(
+            embedded("2002:a9fe:a9fe::"),
+            Some(Ipv4Addr::new(169, 254, 169, 254))
+        );
+        assert_eq!(embedded("2001:db8::1"), None);
+        assert_eq!(embedded("64:ff9b:2::1"), None);
+    }
+
+    #[test]
+    fn allowed_hosts() {
+        assert!(AllowedHost::parse("LocalHost").matches_name("localhost"));
+        assert!(!AllowedHost::parse("localhost").matches_name("example.com"));
+        assert!(!AllowedHost::parse("10.0.0.1").matches_name("10.0.0.1"));
+        // Prefixes which are too long make it a name
+        assert!(AllowedHost::parse("10.0.0.0/33").matches_name("10.0.0.0/33"));
+
+        assert!(network("10.0.0.1", "10.0.0.1"));
+        assert!(!network("10.0.0.1", "10.0.0.2"));
+        assert!(network("[::1]", "::1"));
+        assert!(network("10.0.0.0/8", "10.255.0.1"));
+        assert!(!network("10.0.0.0/8", "11.0.0.1"));
+        assert!(network("fd00::/8", "fd12::1"));
+        assert!(!network("fd00::/8", "fc00::1"));
+        // IPv4 networks never contain IPv6 addresses and the other way around
+        assert!(!network("0.0.0.0/0", "::1"));
+        assert!(!network("::/0", "127.0.0.1"));
+    }
+
+    #[test]
+    fn allowed_host_prefix_edges() {
+        assert!(network("0.0.0.0/0", "127.0.0.1"));
+        assert!(network("0.0.0.0/0", "255.255.255.255"));
+        assert!(network("::/0", "::1"));
+        assert!(network("10.0.0.1/32", "10.0.0.1"));
+        assert!(!network("10.0.0.1/32", "10.0.0.0"));
+        assert!(network("fd00::1/128", "fd00::1"));
+        assert!(!network("fd00::1/128", "fd00::2"));
+    }
+
+    #[test]
+    fn proxied_names() {
+        let check = |url: &str| {
+            check_host(&ParsedUrl::parse(url).expect("url should be valid"), true)
+                .map_err(|e| e.kind())
+        };
+
+        assert_eq!(check("http://example.com/"), Ok(None));
+        for url in &[
+            "http://localhost/",
+            "http://LOCALHOST./",
+            "http://db.localhost/",
+            "http://intranet:8080/",
+            "http://127.0.0.1/",
+            "ftp://example.com/",
+        ] {
+            assert_eq!(check(url), Err(ErrorKind::Forbidden), "{}", url);
+        }
+    }
+
+    #[test]
+    fn masks() {
+        assert!(matches_mask("*!*@*", "nick!user@host"));
+        assert!(matches_mask("nick!*@*", "NICK!user@host"));
+        assert!(matches_mask("n?ck!*", "nick!user@host"));
+        assert!(matches_mask("*@*.example.com", "a!b@c.example.com"));
+        assert!(matches_mask("*a*b", "xxaxxb"));
+        assert!(matches_mask("*", ""));
+        // The rfc1459 casemapping treats []\~ as {}|^
+        assert!(matches_mask("[nick]!*", "{NICK}!user@host"));
+        assert!(matches_mask("a\\b~!*", "A|B^!user@host"));
+
+        assert!(!matches_mask("nick!*@*", "nick2!user@host"));
+        assert!(!matches_mask("n?ck!*", "nck!user@host"));
+        assert!(!matches_mask("*.example.com", "example.com"));
+        assert!(!matches_mask("", "nick"));
     }
 }